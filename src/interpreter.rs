@@ -1,27 +1,63 @@
+mod array;
 mod command;
+mod dict;
+mod file;
+mod list;
+mod output;
+mod string;
 
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::iter;
 use std::marker::PhantomData;
+use std::num::{IntErrorKind, ParseFloatError, ParseIntError};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
 
-use crate::parser::{self, Text, Word};
+use nom::error::ErrorKind;
 
-pub use command::{Command, Puts, Set};
+use crate::parser::{self, ParseError, Text, Word};
+use list::{check_length, substitute_backslashes};
+use output::OutputBuffer;
+
+pub use array::{Array, Parray};
+pub use command::{After, Append, Binary, Command, Encoding, Flush, Incr, Puts, Set, Split};
+pub use dict::Dict;
+pub use file::{File, FileMetadata};
+pub use list::{
+    build_list, build_list_with_limit, split_list, split_list_with_limit, Lappend, Lassign, Lindex,
+    Llength, Lset, Lsort, DEFAULT_LIST_LIMIT,
+};
+pub use string::StringCmd;
 
 pub type EvalResult = Result<String, Error>;
 pub type Variables = HashMap<String, String>;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Error {
-    Arity {
-        cmd: &'static str,
-        expected: usize,
+    WrongArgs {
+        cmd: String,
+        min: usize,
+        max: Option<usize>,
         received: usize,
     },
     UnknownCommand {
         cmd: String,
     },
+    UnknownVariable {
+        name: String,
+    },
+    NotPermitted {
+        cmd: String,
+    },
+    Aborted {
+        cmd: String,
+    },
     Conversion {
         value: String,
         message: &'static str,
@@ -31,9 +67,29 @@ pub enum Error {
         message: &'static str,
         got: Vec<String>,
     },
+    UnknownChannel {
+        channel: String,
+    },
+    InvalidList {
+        list: String,
+        message: &'static str,
+    },
+    ListTooLong {
+        limit: usize,
+    },
+    Io {
+        message: String,
+    },
+    Parse {
+        line: usize,
+        message: String,
+    },
 }
 
 pub trait Context<'a> {
+    /// Evaluate a command that the interpreter doesn't handle itself and that `resolve` didn't
+    /// provide. By default this is `eval_builtin`, so a context only needs to implement the hooks
+    /// it wants to change.
     fn eval(
         &mut self,
         variables: &mut Variables,
@@ -41,18 +97,224 @@ pub trait Context<'a> {
         args: Vec<Cow<'a, str>>,
     ) -> EvalResult
     where
-        Self: Sized;
+        Self: Sized,
+    {
+        eval_builtin(self, variables, cmd, args)
+    }
+
+    /// Provide the implementation of the named command.
+    ///
+    /// This is consulted the first time a command name is used. The returned command is cached
+    /// by the interpreter and used for subsequent calls instead of `eval`. When `None` is
+    /// returned the command is passed to `eval`, and the name is not resolved again until
+    /// `Interpreter::restore` is called.
+    fn resolve(&mut self, _name: &str) -> Option<Box<dyn Command<'a>>> {
+        None
+    }
 
     /// Look up the named variable in the context
     fn get_variable(&self, _name: &str) -> Option<&str> {
         None
     }
+
+    /// Compute the value of a variable that is not otherwise defined.
+    ///
+    /// This is consulted when a variable is substituted that is not in the interpreter's
+    /// variables or returned by `get_variable`. The returned value is stored in the interpreter's
+    /// variables so it is only computed once.
+    fn resolve_var(&mut self, _name: &str) -> Option<String> {
+        None
+    }
+
+    /// The environment variables imported into the `env` array by `Interpreter::import_env`
+    fn env_vars(&self) -> Vec<(String, String)> {
+        env::vars().collect()
+    }
+
+    /// Block for the given duration, used by the `after` command
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+
+    /// Whether the `string` command counts grapheme clusters rather than characters, `false` by
+    /// default
+    #[cfg(feature = "graphemes")]
+    fn graphemes(&self) -> bool {
+        false
+    }
+
+    /// The maximum length of the lists accepted and produced by the list commands and `split`,
+    /// in bytes and in elements, `DEFAULT_LIST_LIMIT` by default
+    fn list_limit(&self) -> usize {
+        DEFAULT_LIST_LIMIT
+    }
+
+    /// The line ending appended by `puts`, `"\n"` by default
+    fn line_ending(&self) -> &str {
+        "\n"
+    }
+
+    /// Look up the metadata of the named file, used by `file exists`, `file size`, and the other
+    /// `file` subcommands that query the filesystem. `None` is returned when the file doesn't
+    /// exist.
+    fn metadata(&self, path: &str) -> Result<Option<FileMetadata>, Error> {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(Error::from(err)),
+        };
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_secs());
+
+        Ok(Some(FileMetadata {
+            is_dir: metadata.is_dir(),
+            is_file: metadata.is_file(),
+            size: metadata.len(),
+            mtime,
+        }))
+    }
+
+    /// Write text to the named channel, used by `puts`
+    fn write(&self, channel: &str, text: &str) -> Result<(), Error> {
+        match channel {
+            "stdout" => io::stdout().write_all(text.as_bytes()),
+            "stderr" => io::stderr().write_all(text.as_bytes()),
+            _ => return Err(unknown_channel(channel)),
+        }
+        .map_err(Error::from)
+    }
+
+    /// Flush any buffered output on the named channel, used by `flush`
+    fn flush(&self, channel: &str) -> Result<(), Error> {
+        match channel {
+            "stdout" => io::stdout().flush(),
+            "stderr" => io::stderr().flush(),
+            _ => return Err(unknown_channel(channel)),
+        }
+        .map_err(Error::from)
+    }
+}
+
+/// Evaluate one of the builtin commands, using `context` for the commands that need the host,
+/// such as `puts` writing through `Context::write`.
+pub fn eval_builtin<'a, C>(
+    context: &C,
+    variables: &mut Variables,
+    cmd: Cow<'a, str>,
+    args: Vec<Cow<'a, str>>,
+) -> EvalResult
+where
+    C: Context<'a>,
+{
+    match &*cmd {
+        "after" => After(context).call(&cmd, variables, args),
+        "append" => Append.call(&cmd, variables, args),
+        "array" => Array.call(&cmd, variables, args),
+        "binary" => Binary.call(&cmd, variables, args),
+        "dict" => Dict.call(&cmd, variables, args),
+        "encoding" => Encoding.call(&cmd, variables, args),
+        "file" => File(context).call(&cmd, variables, args),
+        "set" => Set.call(&cmd, variables, args),
+        "split" => Split {
+            limit: context.list_limit(),
+        }
+        .call(&cmd, variables, args),
+        #[cfg(feature = "graphemes")]
+        "string" => StringCmd {
+            graphemes: context.graphemes(),
+        }
+        .call(&cmd, variables, args),
+        #[cfg(not(feature = "graphemes"))]
+        "string" => StringCmd::default().call(&cmd, variables, args),
+        "flush" => Flush(context).call(&cmd, variables, args),
+        "incr" => Incr.call(&cmd, variables, args),
+        "lappend" => Lappend {
+            limit: context.list_limit(),
+        }
+        .call(&cmd, variables, args),
+        "lassign" => Lassign {
+            limit: context.list_limit(),
+        }
+        .call(&cmd, variables, args),
+        "lindex" => Lindex {
+            limit: context.list_limit(),
+        }
+        .call(&cmd, variables, args),
+        "llength" => Llength {
+            limit: context.list_limit(),
+        }
+        .call(&cmd, variables, args),
+        "lset" => Lset {
+            limit: context.list_limit(),
+        }
+        .call(&cmd, variables, args),
+        "lsort" => Lsort {
+            limit: context.list_limit(),
+        }
+        .call(&cmd, variables, args),
+        "parray" => Parray(context).call(&cmd, variables, args),
+        "puts" => Puts(context).call(&cmd, variables, args),
+        _ => Err(Error::UnknownCommand {
+            cmd: cmd.to_string(),
+        }),
+    }
+}
+
+type Tracer = Box<dyn FnMut(&str, &[Cow<'_, str>])>;
+type StepHook = Box<dyn FnMut(&str, &[Cow<'_, str>], &Variables) -> StepAction>;
+
+/// What the interpreter should do with a command, as decided by the hook set with
+/// `Interpreter::set_step_hook`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepAction {
+    /// Evaluate the command.
+    Continue,
+    /// Don't evaluate the command, and use an empty string as its result.
+    Skip,
+    /// Stop evaluating the script, returning `Error::Aborted`.
+    Abort,
+}
+
+/// Commands that touch the host, which are refused by an interpreter created with `new_safe`.
+const UNSAFE_COMMANDS: &[&str] = &["after", "exec", "open", "source"];
+
+/// Subcommands that touch the host, refused like `UNSAFE_COMMANDS`.
+const UNSAFE_SUBCOMMANDS: &[(&str, &[&str])] = &[(
+    "file",
+    &["exists", "isdirectory", "isfile", "mtime", "size"],
+)];
+
+/// The target command and leading arguments of an alias.
+#[derive(Clone)]
+struct Alias {
+    target: String,
+    args: Vec<String>,
+}
+
+/// The variables and aliases of an interpreter, saved by `Interpreter::snapshot`.
+pub struct Snapshot {
+    variables: Variables,
+    aliases: HashMap<String, Alias>,
+    commands: Vec<String>,
 }
 
 pub struct Interpreter<'a, C: Context<'a>> {
     context: C,
     lifetime: PhantomData<&'a C>,
     variables: Option<Variables>,
+    commands: HashMap<String, Box<dyn Command<'a>>>,
+    /// Names that `Context::resolve` returned `None` for, so it is not asked again
+    unresolved: HashSet<String>,
+    aliases: HashMap<String, Alias>,
+    tracer: Option<Tracer>,
+    step_hook: Option<StepHook>,
+    safe: bool,
+    /// Whether `set_buffered` turned buffering on, restored when capturing stops
+    buffered: bool,
+    buffer: Option<OutputBuffer>,
 }
 
 impl<'a, C> Interpreter<'a, C>
@@ -64,36 +326,337 @@ where
             context,
             lifetime: PhantomData,
             variables: Some(HashMap::new()),
+            commands: HashMap::new(),
+            unresolved: HashSet::new(),
+            aliases: HashMap::new(),
+            tracer: None,
+            step_hook: None,
+            safe: false,
+            buffered: false,
+            buffer: None,
         }
     }
 
+    /// Create an interpreter for untrusted scripts, which returns `Error::NotPermitted` for
+    /// `after`, `exec`, `open`, `source`, and the `file` subcommands that look files up instead
+    /// of passing them to the context.
+    pub fn new_safe(context: C) -> Self {
+        Interpreter {
+            safe: true,
+            ..Interpreter::new(context)
+        }
+    }
+
+    /// Set a function to be called with the command name and substituted arguments before
+    /// each command is evaluated.
+    pub fn set_tracer<F>(&mut self, tracer: F)
+    where
+        F: FnMut(&str, &[Cow<'_, str>]) + 'static,
+    {
+        self.tracer = Some(Box::new(tracer));
+    }
+
+    /// Set a function to be called before each command is evaluated, after the tracer, which
+    /// decides whether the command is evaluated, skipped, or evaluation stops.
+    ///
+    /// The hook receives the command name, the substituted arguments, and the variables. It is
+    /// also called for commands in substitutions, which have already been evaluated by the time
+    /// it sees the command using their result.
+    pub fn set_step_hook<F>(&mut self, hook: F)
+    where
+        F: FnMut(&str, &[Cow<'_, str>], &Variables) -> StepAction + 'static,
+    {
+        self.step_hook = Some(Box::new(hook));
+    }
+
+    /// Buffer the output of `puts` in the interpreter, instead of writing each line to the
+    /// context as it is produced.
+    ///
+    /// Buffered output is written to the context by `flush`, at the end of each call to `eval`
+    /// or `eval_file`, and when buffering is turned off. While buffering, `puts` and `flush` are
+    /// handled by the interpreter rather than the context, and only accept the `stdout` and
+    /// `stderr` channels.
+    ///
+    /// While output is being captured this takes effect when capturing stops.
+    pub fn set_buffered(&mut self, buffered: bool) -> Result<(), Error> {
+        self.buffered = buffered;
+        match &self.buffer {
+            Some(buffer) if buffer.capture => Ok(()),
+            Some(_) if !buffered => {
+                let result = self.flush_output();
+                self.buffer = None;
+                result
+            }
+            None if buffered => {
+                self.buffer = Some(OutputBuffer::new(self.context.line_ending(), false));
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Capture the output of `puts` to `stdout` and `stderr` in the interpreter, to be retrieved
+    /// with `take_output`, instead of writing it to the context. `puts` to any other channel is
+    /// an error.
+    ///
+    /// Any buffered output is written to the context when capturing starts. Output that has not
+    /// been taken is discarded when capturing stops, and output is buffered again if it was
+    /// before.
+    pub fn set_capture(&mut self, capture: bool) -> Result<(), Error> {
+        let capturing = self.buffer.as_ref().is_some_and(|buffer| buffer.capture);
+        if capture && !capturing {
+            self.flush_output()?;
+            self.buffer = Some(OutputBuffer::new(self.context.line_ending(), true));
+        } else if !capture && capturing {
+            self.buffer = if self.buffered {
+                Some(OutputBuffer::new(self.context.line_ending(), false))
+            } else {
+                None
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Remove and return the output captured since capturing started or the last call to
+    /// `take_output`.
+    pub fn take_output(&mut self) -> String {
+        match &self.buffer {
+            Some(buffer) if buffer.capture => buffer
+                .take()
+                .into_iter()
+                .map(|(_channel, text)| text)
+                .collect(),
+            _ => String::new(),
+        }
+    }
+
+    /// Write any buffered output to the context.
+    fn flush_output(&mut self) -> Result<(), Error> {
+        if let Some(buffer) = &self.buffer {
+            if !buffer.capture {
+                for (channel, text) in buffer.take() {
+                    self.context.write(&channel, &text)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Define `name` as an alias for `target`, so that `name extra...` evaluates
+    /// `target args... extra...`. The target may itself be an alias.
+    pub fn alias<I, S>(&mut self, name: &str, target: &str, args: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let alias = Alias {
+            target: target.to_string(),
+            args: args.into_iter().map(Into::into).collect(),
+        };
+        self.aliases.insert(name.to_string(), alias);
+    }
+
+    /// Save the variables and aliases, so that changes made by scripts evaluated afterwards can
+    /// be discarded with `restore`.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            variables: self.variables.clone().unwrap(),
+            aliases: self.aliases.clone(),
+            commands: self.commands.keys().cloned().collect(),
+        }
+    }
+
+    /// Return the variables and aliases to the state saved in `snapshot`. Commands resolved from
+    /// the context since the snapshot was taken are forgotten, and resolved again when next used,
+    /// as are names the context could not resolve.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.variables = Some(snapshot.variables);
+        self.aliases = snapshot.aliases;
+        let commands = snapshot.commands;
+        self.commands.retain(|name, _| commands.contains(name));
+        self.unresolved.clear();
+    }
+
+    /// Set several variables at once, for example to seed a script with values from the host.
+    pub fn set_vars<I>(&mut self, vars: I)
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        self.variables.as_mut().unwrap().extend(vars);
+    }
+
+    /// Set the elements of the `env` array from `Context::env_vars`, so scripts can read
+    /// `$env(HOME)`.
+    pub fn import_env(&mut self) {
+        let vars = self.context.env_vars();
+        self.set_vars(
+            vars.into_iter()
+                .map(|(name, value)| (format!("env({})", name), value)),
+        );
+    }
+
     pub fn eval(&mut self, commands: Vec<parser::Command<'a>>) -> EvalResult {
-        let mut result = String::new();
+        self.eval_commands(commands, |word| word)
+    }
+
+    /// Read, parse, and evaluate the script in the file at `path`.
+    pub fn eval_file<P: AsRef<Path>>(&mut self, path: P) -> EvalResult {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|err| Error::Io {
+            message: format!("{}: {}", path.display(), err),
+        })?;
+        let script = String::from_utf8(bytes).map_err(|err| Error::Io {
+            message: format!(
+                "{}: invalid UTF-8 at byte {}",
+                path.display(),
+                err.utf8_error().valid_up_to()
+            ),
+        })?;
+        let commands = parser::parse(&script).map_err(|err| parse_error(&script, err))?;
+
+        // The script only lives for the duration of this call so the words are converted to
+        // owned values before being handed to the context.
+        self.eval_commands(commands, |word| Cow::from(word.into_owned()))
+    }
+
+    fn eval_commands<'s>(
+        &mut self,
+        commands: Vec<parser::Command<'s>>,
+        into_arg: impl Fn(Cow<'s, str>) -> Cow<'a, str>,
+    ) -> EvalResult {
+        let mut result = Ok(String::new());
         let mut variables = self.variables.take().unwrap();
 
         for command in commands {
-            let mut words = command
-                .0
-                .into_iter()
-                .map(|word| match word {
-                    Word::Bare(fragments) => {
-                        fragments_to_string(fragments, &variables, &self.context, |s| Cow::from(s))
-                    }
-                    Word::Quoted(fragments) => {
-                        fragments_to_string(fragments, &variables, &self.context, |s| unescape(s))
-                    }
-                    Word::Subst(_) => unimplemented!(),
-                })
-                .collect::<Vec<_>>();
-            let args = words.split_off(1);
-
-            result = self
-                .context
-                .eval(&mut variables, words.pop().unwrap(), args)?;
+            result = self.eval_command(&mut variables, command, &into_arg);
+            if result.is_err() {
+                break;
+            }
         }
 
         self.variables.replace(variables);
-        Ok(result)
+        let flushed = self.flush_output();
+        result.and_then(|result| flushed.map(|_| result))
+    }
+
+    /// Substitute the words of `command` and evaluate it, evaluating any command substitutions
+    /// first.
+    fn eval_command<'s>(
+        &mut self,
+        variables: &mut Variables,
+        command: parser::Command<'s>,
+        into_arg: &impl Fn(Cow<'s, str>) -> Cow<'a, str>,
+    ) -> EvalResult {
+        let mut words = Vec::with_capacity(command.0.len());
+
+        for word in command.0 {
+            let word = match word {
+                Word::Bare(fragments) => fragments_to_string(
+                    fragments,
+                    variables,
+                    &mut self.context,
+                    substitute_backslashes,
+                ),
+                Word::Braced(fragments) => {
+                    fragments_to_string(fragments, variables, &mut self.context, Cow::from)
+                }
+                Word::Quoted(fragments) => fragments_to_string(
+                    fragments,
+                    variables,
+                    &mut self.context,
+                    substitute_backslashes,
+                ),
+                Word::Subst(command) => Cow::from(self.eval_command(variables, command, into_arg)?),
+            };
+            words.push(into_arg(word));
+        }
+
+        // An empty command, such as `[{}]`, evaluates to an empty string
+        if words.is_empty() {
+            return Ok(String::new());
+        }
+
+        let mut args = words.split_off(1);
+        let mut cmd = words.pop().unwrap();
+
+        let mut expansions = 0;
+        while let Some(alias) = self.aliases.get(&*cmd) {
+            expansions += 1;
+            if expansions > self.aliases.len() {
+                return Err(Error::Malformed {
+                    cmd: "alias",
+                    message: "alias loop",
+                    got: vec![cmd.to_string()],
+                });
+            }
+            args.splice(0..0, alias.args.iter().cloned().map(Cow::from));
+            cmd = Cow::from(alias.target.clone());
+        }
+
+        if let Some(tracer) = &mut self.tracer {
+            tracer(&cmd, &args);
+        }
+
+        if let Some(hook) = &mut self.step_hook {
+            match hook(&cmd, &args, variables) {
+                StepAction::Continue => {}
+                StepAction::Skip => return Ok(String::new()),
+                StepAction::Abort => {
+                    return Err(Error::Aborted {
+                        cmd: cmd.to_string(),
+                    })
+                }
+            }
+        }
+
+        if self.safe && UNSAFE_COMMANDS.contains(&&*cmd) {
+            return Err(Error::NotPermitted {
+                cmd: cmd.to_string(),
+            });
+        }
+
+        if self.safe {
+            if let (Some((_, subcommands)), Some(subcommand)) = (
+                UNSAFE_SUBCOMMANDS.iter().find(|&&(name, _)| name == cmd),
+                args.first(),
+            ) {
+                if subcommands.contains(&&**subcommand) {
+                    return Err(Error::NotPermitted {
+                        cmd: format!("{} {}", cmd, subcommand),
+                    });
+                }
+            }
+        }
+
+        if let Some(buffer) = &self.buffer {
+            match &*cmd {
+                "parray" => return Parray(buffer).call(&cmd, variables, args),
+                "puts" => return Puts(buffer).call(&cmd, variables, args),
+                "flush" => {
+                    self.flush_output()?;
+                    return Flush(&self.context).call(&cmd, variables, args);
+                }
+                _ => {}
+            }
+        }
+
+        if !self.commands.contains_key(&*cmd) && !self.unresolved.contains(&*cmd) {
+            match self.context.resolve(&cmd) {
+                Some(command) => {
+                    self.commands.insert(cmd.to_string(), command);
+                }
+                None => {
+                    self.unresolved.insert(cmd.to_string());
+                }
+            }
+        }
+
+        match self.commands.get(&*cmd) {
+            Some(command) => command.call(&cmd, variables, args),
+            None => self.context.eval(variables, cmd, args),
+        }
     }
 
     pub fn context(&self) -> &C {
@@ -106,70 +669,208 @@ where
 }
 
 /// Perform variable substitution and return the resulting concatenated result.
-fn fragments_to_string<'a>(
+///
+/// A word made of a single literal fragment is borrowed from the script rather than copied,
+/// unless `transform` needs to change it.
+///
+/// `transform` is applied to literal text, which allows backslash processing to be skipped for
+/// words where the backslashes are literal.
+fn fragments_to_string<'a, 'c>(
     fragments: Vec<Text<'a>>,
-    variables: &Variables,
-    context: &impl Context<'a>,
-    transform: impl Fn(&str) -> Cow<'_, str>,
+    variables: &mut Variables,
+    context: &mut impl Context<'c>,
+    transform: impl Fn(&'a str) -> Cow<'a, str>,
 ) -> Cow<'a, str> {
     if let [Text::Text(text)] = fragments.as_slice() {
-        Cow::from(*text)
+        transform(text)
     } else {
-        let string = fragments
-            .into_iter()
-            .fold(String::new(), |mut string, fragment| {
-                match fragment {
-                    Text::Text(s) => string.push_str(&transform(s)),
-                    Text::Variable(name) => {
-                        string.push_str(variables.get(name).map(String::as_str).or_else(|| context.get_variable(name)).unwrap_or("")) // TODO: Perhaps add a mode that returns an error on undefined variable
-                    }
+        let mut string = String::new();
+        for fragment in fragments {
+            match fragment {
+                Text::Text(s) => string.push_str(&transform(s)),
+                Text::Variable(name) => push_variable(&mut string, name, variables, context),
+                Text::Element(name, index) => {
+                    let index = fragments_to_string(index, variables, context, Cow::from);
+                    let name = format!("{}({})", name, index);
+                    push_variable(&mut string, &name, variables, context)
                 }
-                string
-            });
+            }
+        }
         Cow::from(string)
     }
 }
 
-/// Processes backslash escapes.
-fn unescape(escaped: &str) -> Cow<'_, str> {
-    // Benchmarks show that this check is worth it given the common case of text with
-    // no escape characters.
-    if escaped.contains('\\') {
-        let mut result = String::with_capacity(escaped.len());
-        let mut chars = escaped.chars();
-
-        loop {
-            match chars.next() {
-                Some('\\') => match chars.next().expect("FIXME: truncated escape sequence") {
-                    '\\' => result.push('\\'),
-                    '"' => result.push('"'),
-                    'n' => result.push('\n'),
-                    c => panic!("invalid escape sequence '{}'", c),
-                },
-                Some(c) => result.push(c),
-                None => break,
-            };
+/// Append the value of the named variable to `string`.
+fn push_variable<'c>(
+    string: &mut String,
+    name: &str,
+    variables: &mut Variables,
+    context: &mut impl Context<'c>,
+) {
+    if !variables.contains_key(name) && context.get_variable(name).is_none() {
+        if let Some(value) = context.resolve_var(name) {
+            variables.insert(name.to_string(), value);
         }
+    }
+    string.push_str(
+        variables
+            .get(name)
+            .map(String::as_str)
+            .or_else(|| context.get_variable(name))
+            .unwrap_or(""),
+    ) // TODO: Perhaps add a mode that returns an error on undefined variable
+}
+
+fn unknown_channel(channel: &str) -> Error {
+    Error::UnknownChannel {
+        channel: channel.to_string(),
+    }
+}
 
-        Cow::from(result)
+/// Parse a Tcl integer.
+///
+/// An optional sign may be followed by decimal digits, or digits in another base indicated by a
+/// `0x` (hexadecimal), `0o` or `0` (octal), or `0b` (binary) prefix. Surrounding whitespace is
+/// ignored.
+pub fn parse_tcl_int(value: &str) -> Result<i64, Error> {
+    let error = |message| Error::Conversion {
+        value: value.to_string(),
+        message,
+    };
+
+    let trimmed = value.trim();
+    let (negative, unsigned) = match trimmed.as_bytes().first() {
+        Some(b'-') => (true, &trimmed[1..]),
+        Some(b'+') => (false, &trimmed[1..]),
+        _ => (false, trimmed),
+    };
+    let (radix, digits) = match unsigned.get(..2) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("0x") => (16, &unsigned[2..]),
+        Some(prefix) if prefix.eq_ignore_ascii_case("0o") => (8, &unsigned[2..]),
+        Some(prefix) if prefix.eq_ignore_ascii_case("0b") => (2, &unsigned[2..]),
+        Some(prefix) if prefix.starts_with('0') => (8, &unsigned[1..]),
+        _ => (10, unsigned),
+    };
+
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(error("expected integer"));
+    }
+
+    let magnitude =
+        u64::from_str_radix(digits, radix).map_err(|_| error("integer value too large"))?;
+    if negative && magnitude <= i64::MAX as u64 + 1 {
+        Ok((magnitude as i64).wrapping_neg())
+    } else if !negative && magnitude <= i64::MAX as u64 {
+        Ok(magnitude as i64)
     } else {
-        Cow::from(escaped)
+        Err(error("integer value too large"))
     }
 }
 
+/// Resolve a Tcl index into a collection of `len` items.
+///
+/// The index may be an integer, `end`, or `end-N`/`end+N`. Returns `Ok(None)` when the index is
+/// outside the collection and `Error::Conversion` when it is malformed.
+pub fn resolve_index(spec: &str, len: usize) -> Result<Option<usize>, Error> {
+    let index = index_offset(spec, len).ok_or_else(|| bad_index(spec))?;
+    Ok(if index >= 0 && index < len as i64 {
+        Some(index as usize)
+    } else {
+        None
+    })
+}
+
+pub(crate) fn bad_index(spec: &str) -> Error {
+    Error::Conversion {
+        value: spec.to_string(),
+        message: "bad index: must be integer?[+-]integer? or end?[+-]integer?",
+    }
+}
+
+/// An error for a subcommand of `cmd` called with the wrong arguments.
+pub(crate) fn usage(
+    cmd: &'static str,
+    subcommand: &'static str,
+    message: &'static str,
+    args: &[Cow<'_, str>],
+) -> Error {
+    Error::Malformed {
+        cmd,
+        message,
+        got: iter::once(subcommand)
+            .chain(args.iter().map(AsRef::as_ref))
+            .map(str::to_string)
+            .collect(),
+    }
+}
+
+/// Convert string slices to command arguments, for tests that call commands directly.
+#[cfg(test)]
+pub(crate) fn to_args<'a>(args: &[&'a str]) -> Vec<Cow<'a, str>> {
+    args.iter().map(|&arg| Cow::from(arg)).collect()
+}
+
+/// Resolve a Tcl index without bounds checking, for commands that clamp or extend.
+pub(crate) fn index_offset(spec: &str, len: usize) -> Option<i64> {
+    let spec = spec.trim();
+    if !spec.starts_with("end") {
+        return parse_tcl_int(spec).ok();
+    }
+
+    let last = len as i64 - 1;
+    match &spec[3..] {
+        "" => Some(last),
+        offset if offset.starts_with('-') || offset.starts_with('+') => {
+            // Reject forms like end--1 or end- 1 that parse_tcl_int would otherwise accept
+            if offset[1..].starts_with(|c: char| c == '-' || c == '+' || c.is_whitespace()) {
+                return None;
+            }
+            parse_tcl_int(offset)
+                .ok()
+                .and_then(|offset| last.checked_add(offset))
+        }
+        _ => None,
+    }
+}
+
+/// Describe where `parser::parse` failed in `script`.
+fn parse_error(script: &str, err: nom::Err<(&str, ErrorKind)>) -> Error {
+    let remaining = match err {
+        nom::Err::Error((remaining, _)) | nom::Err::Failure((remaining, _)) => remaining,
+        nom::Err::Incomplete(_) => "",
+    };
+    let ParseError { line, message } = ParseError::at(script, remaining);
+
+    Error::Parse { line, message }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         match self {
-            Error::Arity {
+            Error::WrongArgs {
                 cmd,
-                expected,
+                min,
+                max,
                 received,
-            } => write!(
-                f,
-                "Expected {} arguments to '{}', received {}",
-                expected, cmd, received
-            ),
+            } => {
+                write!(f, "Expected ")?;
+                match max {
+                    Some(max) if max == min => write!(f, "{}", min)?,
+                    Some(max) => write!(f, "{} to {}", min, max)?,
+                    None => write!(f, "at least {}", min)?,
+                }
+                write!(f, " arguments to '{}', received {}", cmd, received)
+            }
             Error::UnknownCommand { cmd } => write!(f, "Unknown command '{}'", cmd),
+            Error::UnknownVariable { name } => write!(f, "Unknown variable '{}'", name),
+            Error::NotPermitted { cmd } => {
+                write!(
+                    f,
+                    "Command '{}' is not permitted in a safe interpreter",
+                    cmd
+                )
+            }
+            Error::Aborted { cmd } => write!(f, "Evaluation aborted before '{}'", cmd),
             Error::Conversion { value, message } => {
                 write!(f, "Unable to convert '{}': {}", value, message)
             }
@@ -180,29 +881,76 @@ impl fmt::Display for Error {
                 message,
                 got.join(" ")
             ),
+            Error::UnknownChannel { channel } => write!(f, "Unknown channel '{}'", channel),
+            Error::InvalidList { list, message } => {
+                write!(f, "Invalid list '{}': {}", list, message)
+            }
+            Error::ListTooLong { limit } => {
+                write!(f, "List is longer than the limit of {} bytes", limit)
+            }
+            Error::Io { message } => write!(f, "I/O error: {}", message),
+            Error::Parse { line, message } => {
+                write!(f, "Parse error on line {}: {}", line, message)
+            }
         }
     }
 }
 
 impl std::error::Error for Error {}
 
+// The standard parse errors don't include the text that failed to parse, so the value of these
+// conversion errors is empty. Commands that want to report the value construct
+// `Error::Conversion` themselves.
+impl From<ParseIntError> for Error {
+    fn from(err: ParseIntError) -> Self {
+        let message = match err.kind() {
+            IntErrorKind::Empty => "expected integer but got empty string",
+            IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => "integer value too large",
+            _ => "expected integer",
+        };
+        Error::Conversion {
+            value: String::new(),
+            message,
+        }
+    }
+}
+
+impl From<ParseFloatError> for Error {
+    fn from(_err: ParseFloatError) -> Self {
+        Error::Conversion {
+            value: String::new(),
+            message: "expected floating-point number",
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io {
+            message: err.to_string(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use easybench::bench;
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
     struct Get;
 
     impl<'a> Command<'a> for Get {
-        fn eval(&self, variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
-            if args.len() != 1 {
-                return Err(Error::Arity {
-                    cmd: "get",
-                    expected: 1,
-                    received: args.len(),
-                });
-            }
+        fn min_args(&self) -> usize {
+            1
+        }
+
+        fn max_args(&self) -> Option<usize> {
+            Some(1)
+        }
 
+        fn eval(&self, variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
             match variables.get(&*args[0]) {
                 Some(var) => Ok(var.to_string()),
                 None => Ok(String::new()),
@@ -210,6 +958,17 @@ mod tests {
         }
     }
 
+    struct Sum;
+
+    impl<'a> Command<'a> for Sum {
+        fn eval(&self, _variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+            args.iter()
+                .map(|arg| parse_tcl_int(arg))
+                .sum::<Result<i64, _>>()
+                .map(|sum| sum.to_string())
+        }
+    }
+
     struct TestContext;
 
     impl<'a> Context<'a> for TestContext {
@@ -220,8 +979,10 @@ mod tests {
             args: Vec<Cow<'a, str>>,
         ) -> EvalResult {
             match &*cmd {
-                "set" => Set.eval(variables, args),
-                "get" => Get.eval(variables, args),
+                "set" => Set.call(&cmd, variables, args),
+                "get" => Get.call(&cmd, variables, args),
+                "+" => Sum.call(&cmd, variables, args),
+                "string" => StringCmd::default().call(&cmd, variables, args),
                 _ => Err(Error::UnknownCommand {
                     cmd: cmd.to_string(),
                 }),
@@ -229,6 +990,83 @@ mod tests {
         }
     }
 
+    /// Relies on the default `eval` and records the calls made to its hooks.
+    #[derive(Default)]
+    struct RecordingContext {
+        resolved: Vec<String>,
+        resolved_vars: Vec<String>,
+        slept: RefCell<Vec<Duration>>,
+        written: RefCell<Vec<String>>,
+        flushed: RefCell<Vec<String>>,
+        crlf: bool,
+        list_limit: Option<usize>,
+        #[cfg(feature = "graphemes")]
+        graphemes: bool,
+    }
+
+    impl<'a> Context<'a> for RecordingContext {
+        fn resolve(&mut self, name: &str) -> Option<Box<dyn Command<'a>>> {
+            self.resolved.push(name.to_string());
+            match name {
+                "get" => Some(Box::new(Get)),
+                "+" => Some(Box::new(Sum)),
+                _ => None,
+            }
+        }
+
+        fn resolve_var(&mut self, name: &str) -> Option<String> {
+            self.resolved_vars.push(name.to_string());
+            if name == "date" {
+                Some("2019-06-01".to_string())
+            } else {
+                None
+            }
+        }
+
+        fn env_vars(&self) -> Vec<(String, String)> {
+            vec![
+                ("HOME".to_string(), "/home/tcl".to_string()),
+                ("KEY".to_string(), "value".to_string()),
+            ]
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.slept.borrow_mut().push(duration);
+        }
+
+        #[cfg(feature = "graphemes")]
+        fn graphemes(&self) -> bool {
+            self.graphemes
+        }
+
+        fn list_limit(&self) -> usize {
+            self.list_limit.unwrap_or(DEFAULT_LIST_LIMIT)
+        }
+
+        fn line_ending(&self) -> &str {
+            if self.crlf {
+                "\r\n"
+            } else {
+                "\n"
+            }
+        }
+
+        fn write(&self, channel: &str, text: &str) -> Result<(), Error> {
+            self.written
+                .borrow_mut()
+                .push(format!("{}: {}", channel, text));
+            Ok(())
+        }
+
+        fn flush(&self, channel: &str) -> Result<(), Error> {
+            if channel != "stdout" {
+                return Err(unknown_channel(channel));
+            }
+            self.flushed.borrow_mut().push(channel.to_string());
+            Ok(())
+        }
+    }
+
     #[test]
     fn test_interpret_variable() {
         let input = "set example indirect\nset indirect found\nget $example";
@@ -247,6 +1085,757 @@ mod tests {
         assert_eq!(tcl.eval(script).unwrap(), "found".to_string());
     }
 
+    #[test]
+    fn test_interpret_command_substitution() {
+        let input = "set a 2\nset b 3\nset total [ + $a $b ]\nget total";
+        let script = parser::parse(input).unwrap();
+        let mut tcl = Interpreter::new(TestContext);
+        assert_eq!(tcl.eval(script).unwrap(), "5".to_string());
+
+        let script =
+            parser::parse("set total [ + 1 [ + $a $b ] [ get total ] ]\nget total").unwrap();
+        assert_eq!(tcl.eval(script).unwrap(), "11".to_string());
+
+        let script = parser::parse("set total [ + $a x ]").unwrap();
+        assert!(tcl.eval(script).is_err());
+        let script = parser::parse("get total").unwrap();
+        assert_eq!(tcl.eval(script).unwrap(), "11".to_string());
+    }
+
+    #[test]
+    fn test_interpret_escaped_dollar() {
+        let mut tcl = Interpreter::new(TestContext);
+        let script = parser::parse("set x \"\\$5\"\nget x").unwrap();
+        assert_eq!(tcl.eval(script), Ok("$5".to_string()));
+
+        let script = parser::parse("set c see\nset x \"a\\$b $c\"\nget x").unwrap();
+        assert_eq!(tcl.eval(script), Ok("a$b see".to_string()));
+    }
+
+    #[test]
+    fn test_interpret_quoted_backslashes() {
+        let mut tcl = Interpreter::new(TestContext);
+        let script = parser::parse("set x \"a\\tb\\x41\\q\"\nget x").unwrap();
+        assert_eq!(tcl.eval(script), Ok("a\tbAq".to_string()));
+
+        let script = parser::parse("set x \"a\\\n    b\"\nget x").unwrap();
+        assert_eq!(tcl.eval(script), Ok("a b".to_string()));
+    }
+
+    #[test]
+    fn test_interpret_empty_command_substitution() {
+        let script = parser::parse("set x [{}]\nget x").unwrap();
+        let mut tcl = Interpreter::new(TestContext);
+        assert_eq!(tcl.eval(script), Ok(String::new()));
+        let script = parser::parse("set y [{ }]\nget y").unwrap();
+        assert_eq!(tcl.eval(script), Ok(String::new()));
+    }
+
+    #[test]
+    fn test_interpret_backslashes() {
+        let script = parser::parse(r#"set example "a\nb""#).unwrap();
+        let mut tcl = Interpreter::new(TestContext);
+        tcl.eval(script).unwrap();
+        let script = parser::parse("get example").unwrap();
+        assert_eq!(tcl.eval(script).unwrap(), "a\nb".to_string());
+
+        let script = parser::parse(r#"set example "$example\"$example\"""#).unwrap();
+        tcl.eval(script).unwrap();
+        let script = parser::parse("get example").unwrap();
+        assert_eq!(tcl.eval(script).unwrap(), "a\nb\"a\nb\"".to_string());
+
+        let script = parser::parse(r"set example {a\nb}").unwrap();
+        tcl.eval(script).unwrap();
+        let script = parser::parse("get example").unwrap();
+        assert_eq!(tcl.eval(script).unwrap(), r"a\nb".to_string());
+
+        let script = parser::parse(r"set example a\ b\tc\$example\[d\]").unwrap();
+        tcl.eval(script).unwrap();
+        let script = parser::parse("get example").unwrap();
+        assert_eq!(tcl.eval(script).unwrap(), "a b\tc$example[d]".to_string());
+    }
+
+    #[test]
+    fn test_resolve_caches_commands() {
+        let input = "set a 1\nset b [ + $a [ get a ] ]\nget b";
+        let script = parser::parse(input).unwrap();
+        let mut tcl = Interpreter::new(RecordingContext::default());
+        assert_eq!(tcl.eval(script).unwrap(), "2".to_string());
+        let script = parser::parse("get a\nget a").unwrap();
+        assert_eq!(tcl.eval(script).unwrap(), "1".to_string());
+        assert_eq!(tcl.context().resolved, vec!["set", "get", "+"]);
+
+        let script = parser::parse("missing").unwrap();
+        assert_eq!(
+            tcl.eval(script),
+            Err(Error::UnknownCommand {
+                cmd: "missing".to_string()
+            })
+        );
+        let script = parser::parse("set c 3\nmissing").unwrap();
+        assert!(tcl.eval(script).is_err());
+        assert_eq!(tcl.context().resolved, vec!["set", "get", "+", "missing"]);
+    }
+
+    #[test]
+    fn test_eval_file() {
+        let path = std::env::temp_dir().join(format!("tcl-eval-file-{}.tcl", std::process::id()));
+        fs::write(
+            &path,
+            "set example indirect\nset indirect found\nget $example\n",
+        )
+        .unwrap();
+        let mut tcl = Interpreter::new(TestContext);
+        let result = tcl.eval_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result, Ok("found".to_string()));
+
+        // Variables persist between evaluations
+        let script = parser::parse("get indirect").unwrap();
+        assert_eq!(tcl.eval(script), Ok("found".to_string()));
+    }
+
+    #[test]
+    fn test_eval_file_errors() {
+        let mut tcl = Interpreter::new(TestContext);
+        match tcl.eval_file("/this/file/does/not/exist.tcl") {
+            Err(Error::Io { .. }) => {}
+            other => panic!("expected Error::Io, got {:?}", other),
+        }
+
+        let path = std::env::temp_dir().join(format!("tcl-parse-error-{}.tcl", std::process::id()));
+        fs::write(&path, "set a 1\nset b }\n").unwrap();
+        let result = tcl.eval_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            result,
+            Err(Error::Parse {
+                line: 2,
+                message: "unexpected '}'".to_string()
+            })
+        );
+
+        let path = std::env::temp_dir().join(format!("tcl-utf8-error-{}.tcl", std::process::id()));
+        fs::write(&path, b"set a \xc3\xa9\nset b \xff\n").unwrap();
+        let result = tcl.eval_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            result,
+            Err(Error::Io {
+                message: format!("{}: invalid UTF-8 at byte 15", path.display())
+            })
+        );
+    }
+
+    struct Half;
+
+    impl<'a> Command<'a> for Half {
+        fn eval(&self, _variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+            let value = match args.as_slice() {
+                [value, kind] if kind == "int" => f64::from(value.parse::<i8>()?),
+                [value, _] => value.parse::<f64>()?,
+                _ => return Err(io::Error::other("missing value").into()),
+            };
+            Ok((value / 2.0).to_string())
+        }
+    }
+
+    #[test]
+    fn test_error_conversions() {
+        let half = |args: &[&'static str]| Half.eval(&mut Variables::new(), to_args(args));
+        assert_eq!(half(&["5", "int"]), Ok("2.5".to_string()));
+        assert_eq!(half(&["0.5", "float"]), Ok("0.25".to_string()));
+        assert_eq!(
+            half(&["x", "int"]),
+            Err(Error::Conversion {
+                value: String::new(),
+                message: "expected integer"
+            })
+        );
+        assert_eq!(
+            half(&["300", "int"]),
+            Err(Error::Conversion {
+                value: String::new(),
+                message: "integer value too large"
+            })
+        );
+        assert_eq!(
+            half(&["1.2.3", "float"]),
+            Err(Error::Conversion {
+                value: String::new(),
+                message: "expected floating-point number"
+            })
+        );
+        assert_eq!(
+            half(&[]),
+            Err(Error::Io {
+                message: "missing value".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_tcl_int() {
+        assert_eq!(parse_tcl_int("42"), Ok(42));
+        assert_eq!(parse_tcl_int(" -42 "), Ok(-42));
+        assert_eq!(parse_tcl_int("+7"), Ok(7));
+        assert_eq!(parse_tcl_int("0"), Ok(0));
+        assert_eq!(parse_tcl_int("0xff"), Ok(255));
+        assert_eq!(parse_tcl_int("-0XFF"), Ok(-255));
+        assert_eq!(parse_tcl_int("0o17"), Ok(15));
+        assert_eq!(parse_tcl_int("017"), Ok(15));
+        assert_eq!(parse_tcl_int("0b1010"), Ok(10));
+        assert_eq!(parse_tcl_int("-9223372036854775808"), Ok(i64::MIN));
+        assert_eq!(
+            parse_tcl_int("12abc"),
+            Err(Error::Conversion {
+                value: "12abc".to_string(),
+                message: "expected integer"
+            })
+        );
+        assert!(parse_tcl_int("").is_err());
+        assert!(parse_tcl_int("0x").is_err());
+        assert!(parse_tcl_int("09").is_err());
+        assert!(parse_tcl_int("--1").is_err());
+        assert!(parse_tcl_int("9223372036854775808").is_err());
+    }
+
+    #[test]
+    fn test_resolve_index() {
+        assert_eq!(resolve_index("0", 3), Ok(Some(0)));
+        assert_eq!(resolve_index("2", 3), Ok(Some(2)));
+        assert_eq!(resolve_index("end", 3), Ok(Some(2)));
+        assert_eq!(resolve_index("end-2", 3), Ok(Some(0)));
+        assert_eq!(resolve_index("end+0", 3), Ok(Some(2)));
+        assert_eq!(resolve_index("end-3", 3), Ok(None));
+        assert_eq!(resolve_index("end+1", 3), Ok(None));
+        assert_eq!(resolve_index("3", 3), Ok(None));
+        assert_eq!(resolve_index("-1", 3), Ok(None));
+        assert_eq!(resolve_index("end", 0), Ok(None));
+        assert_eq!(resolve_index("end--1", 3), Err(bad_index("end--1")));
+        assert_eq!(resolve_index("ending", 3), Err(bad_index("ending")));
+        assert_eq!(resolve_index("one", 3), Err(bad_index("one")));
+        assert_eq!(index_offset("end+1", 3), Some(3));
+        assert_eq!(index_offset("-5", 3), Some(-5));
+    }
+
+    #[test]
+    fn test_interpret_trailing_backslash() {
+        let script = parser::parse("set example a\\").unwrap();
+        let mut tcl = Interpreter::new(TestContext);
+        tcl.eval(script).unwrap();
+        let script = parser::parse("get example").unwrap();
+        assert_eq!(tcl.eval(script).unwrap(), "a\\".to_string());
+
+        // A backslash-newline continues the command instead
+        let script = parser::parse("set example a \\\n b").unwrap();
+        assert!(tcl.eval(script).is_err());
+    }
+
+    #[test]
+    fn test_set_vars() {
+        let script = parser::parse("set a \"$first $second $third\"\nget a").unwrap();
+        let mut tcl = Interpreter::new(TestContext);
+        tcl.set_vars(vec![
+            ("first".to_string(), "1".to_string()),
+            ("second".to_string(), "two".to_string()),
+            ("third".to_string(), "3 4".to_string()),
+        ]);
+        assert_eq!(tcl.eval(script), Ok("1 two 3 4".to_string()));
+    }
+
+    #[test]
+    fn test_resolve_var() {
+        let input = "set a \"on $date\"\nset b $date\nset c \"$missing$missing\"\nget a";
+        let script = parser::parse(input).unwrap();
+        let mut tcl = Interpreter::new(RecordingContext::default());
+        assert_eq!(tcl.eval(script), Ok("on 2019-06-01".to_string()));
+        assert_eq!(
+            tcl.context().resolved_vars,
+            vec!["date", "missing", "missing"]
+        );
+
+        let script = parser::parse("get b").unwrap();
+        assert_eq!(tcl.eval(script), Ok("2019-06-01".to_string()));
+    }
+
+    #[test]
+    fn test_import_env() {
+        let script = parser::parse("set a \"$env(KEY) in $env(HOME)/src\"\nget a").unwrap();
+        let mut tcl = Interpreter::new(RecordingContext::default());
+        tcl.import_env();
+        assert_eq!(tcl.eval(script), Ok("value in /home/tcl/src".to_string()));
+
+        let script = parser::parse("set env(KEY) changed\nget env(KEY)").unwrap();
+        assert_eq!(tcl.eval(script), Ok("changed".to_string()));
+
+        let script = parser::parse("set k HOME\nset y $env($k)\nget y").unwrap();
+        assert_eq!(tcl.eval(script), Ok("/home/tcl".to_string()));
+    }
+
+    #[test]
+    fn test_array_element_variable_index() {
+        let input = "set i 2\nset a(x,2) found\nset b \"$a(x,$i)!\"\nget b";
+        let script = parser::parse(input).unwrap();
+        let mut tcl = Interpreter::new(TestContext);
+        assert_eq!(tcl.eval(script), Ok("found!".to_string()));
+    }
+
+    #[test]
+    fn test_alias() {
+        let mut tcl = Interpreter::new(TestContext);
+        tcl.alias("inc", "+", vec!["1"]);
+        tcl.alias("inc10", "inc", vec!["9"]);
+        tcl.alias("setx", "set", vec!["x"]);
+        let script = parser::parse("setx [inc 4]\nget x").unwrap();
+        assert_eq!(tcl.eval(script), Ok("5".to_string()));
+        let script = parser::parse("inc10 5").unwrap();
+        assert_eq!(tcl.eval(script), Ok("15".to_string()));
+
+        tcl.alias("a", "b", Vec::<String>::new());
+        tcl.alias("b", "a", Vec::<String>::new());
+        let script = parser::parse("a").unwrap();
+        assert!(tcl.eval(script).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let mut tcl = Interpreter::new(RecordingContext::default());
+        let script = parser::parse("set a 1\nget a").unwrap();
+        tcl.eval(script).unwrap();
+        let snapshot = tcl.snapshot();
+
+        tcl.alias("inc", "+", vec!["1"]);
+        let script = parser::parse("set a 2\nset b [inc 2]").unwrap();
+        tcl.eval(script).unwrap();
+        tcl.restore(snapshot);
+        assert!(tcl.unresolved.is_empty());
+
+        let script = parser::parse("get a").unwrap();
+        assert_eq!(tcl.eval(script), Ok("1".to_string()));
+        let script = parser::parse("get b").unwrap();
+        assert_eq!(tcl.eval(script), Ok(String::new()));
+        let script = parser::parse("inc 1").unwrap();
+        assert!(tcl.eval(script).is_err());
+        assert!(tcl.commands.contains_key("get"));
+        assert!(!tcl.commands.contains_key("+"));
+    }
+
+    #[test]
+    fn test_tracer() {
+        let input = "set a 1\nset b \"$a $a\"\nget b";
+        let script = parser::parse(input).unwrap();
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        let mut tcl = Interpreter::new(TestContext);
+        let recorder = Rc::clone(&trace);
+        tcl.set_tracer(move |cmd, args| {
+            recorder
+                .borrow_mut()
+                .push(format!("{}: {}", cmd, args.join("|")))
+        });
+        assert_eq!(tcl.eval(script).unwrap(), "1 1".to_string());
+        assert_eq!(*trace.borrow(), vec!["set: a|1", "set: b|1 1", "get: b"]);
+    }
+
+    #[test]
+    fn test_default_metadata_reads_filesystem() {
+        let manifest = TestContext.metadata("Cargo.toml").unwrap().unwrap();
+        assert!(manifest.is_file && !manifest.is_dir && manifest.size > 0);
+        assert!(TestContext.metadata("src").unwrap().unwrap().is_dir);
+        assert_eq!(TestContext.metadata("no/such/file"), Ok(None));
+    }
+
+    #[test]
+    fn test_context_list_limit() {
+        let limited = || {
+            Interpreter::new(RecordingContext {
+                list_limit: Some(8),
+                ..RecordingContext::default()
+            })
+        };
+
+        let script = parser::parse("lappend l a b c d\nllength $l").unwrap();
+        assert_eq!(limited().eval(script), Ok("4".to_string()));
+        let script = parser::parse("lappend l a b c d e").unwrap();
+        assert_eq!(limited().eval(script), Err(Error::ListTooLong { limit: 8 }));
+        let script = parser::parse("split \"a b c d e\"").unwrap();
+        assert_eq!(limited().eval(script), Err(Error::ListTooLong { limit: 8 }));
+    }
+
+    #[test]
+    fn test_default_eval_writes_through_context() {
+        let input = "set a(x) 1\nappend greeting hello\nputs $greeting\nputs -nonewline stderr [string length $greeting]\nparray a";
+        let mut tcl = Interpreter::new(RecordingContext {
+            crlf: true,
+            ..RecordingContext::default()
+        });
+        assert_eq!(tcl.eval(parser::parse(input).unwrap()), Ok(String::new()));
+        assert_eq!(
+            *tcl.context().written.borrow(),
+            vec!["stdout: hello\r\n", "stderr: 5", "stdout: a(x) = 1\r\n",]
+        );
+        assert_eq!(
+            tcl.eval(parser::parse("nope").unwrap()),
+            Err(Error::UnknownCommand {
+                cmd: "nope".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_step_hook() {
+        let input = "set a 1\nset b 2\nset c 3\nset d 4";
+        let get = |tcl: &mut Interpreter<'static, TestContext>, script: &'static str| {
+            tcl.set_step_hook(|_cmd, _args, _variables| StepAction::Continue);
+            tcl.eval(parser::parse(script).unwrap()).unwrap()
+        };
+
+        let mut tcl = Interpreter::new(TestContext);
+        let mut steps = 0;
+        tcl.set_step_hook(move |_cmd, _args, _variables| {
+            steps += 1;
+            if steps > 2 {
+                StepAction::Abort
+            } else {
+                StepAction::Continue
+            }
+        });
+        assert_eq!(
+            tcl.eval(parser::parse(input).unwrap()),
+            Err(Error::Aborted {
+                cmd: "set".to_string()
+            })
+        );
+        assert_eq!(get(&mut tcl, "get b"), "2");
+        assert_eq!(get(&mut tcl, "get c"), "");
+        assert_eq!(get(&mut tcl, "get d"), "");
+
+        let mut tcl = Interpreter::new(TestContext);
+        tcl.set_step_hook(|_cmd, args, variables| {
+            if args.first().map(|arg| arg.as_ref()) == Some("b") || variables.contains_key("c") {
+                StepAction::Skip
+            } else {
+                StepAction::Continue
+            }
+        });
+        assert_eq!(tcl.eval(parser::parse(input).unwrap()), Ok(String::new()));
+        assert_eq!(get(&mut tcl, "get a"), "1");
+        assert_eq!(get(&mut tcl, "get b"), "");
+        assert_eq!(get(&mut tcl, "get c"), "3");
+        assert_eq!(get(&mut tcl, "get d"), "");
+    }
+
+    #[test]
+    fn test_string_map_sees_substituted_values() {
+        let input = "set name World\nset greeting \"Hello, $name\"\nset map \"$name Tcl\"\nstring map \"Hello Goodbye $map\" [get greeting]";
+        let script = parser::parse(input).unwrap();
+        let mut tcl = Interpreter::new(TestContext);
+        assert_eq!(tcl.eval(script).unwrap(), "Goodbye, Tcl".to_string());
+
+        let script = parser::parse("string map \"a $name\" aXa").unwrap();
+        assert_eq!(tcl.eval(script).unwrap(), "WorldXWorld".to_string());
+    }
+
+    #[test]
+    fn test_literal_words_are_borrowed() {
+        let input = "set a \"x\"\nset \"b\" \"$a y\"\nset c [get a]";
+        let script = parser::parse(input).unwrap();
+        let borrowed = Rc::new(RefCell::new(Vec::new()));
+        let mut tcl = Interpreter::new(TestContext);
+        let recorder = Rc::clone(&borrowed);
+        tcl.set_tracer(move |_cmd, args| {
+            recorder.borrow_mut().push(
+                args.iter()
+                    .map(|arg| matches!(arg, Cow::Borrowed(_)))
+                    .collect::<Vec<_>>(),
+            )
+        });
+        tcl.eval(script).unwrap();
+        assert_eq!(
+            *borrowed.borrow(),
+            vec![
+                vec![true, true],
+                vec![true, false],
+                vec![true],
+                vec![true, false]
+            ]
+        );
+
+        let mut context = TestContext;
+        let mut variables = Variables::new();
+        let literal = vec![Text::Text("literal")];
+        assert!(matches!(
+            fragments_to_string(
+                literal,
+                &mut variables,
+                &mut context,
+                substitute_backslashes
+            ),
+            Cow::Borrowed("literal")
+        ));
+        let escaped = vec![Text::Text("a\\nb")];
+        assert!(matches!(
+            fragments_to_string(
+                escaped,
+                &mut variables,
+                &mut context,
+                substitute_backslashes
+            ),
+            Cow::Owned(_)
+        ));
+    }
+
+    #[test]
+    fn test_puts_line_ending() {
+        let script = parser::parse("puts a\nputs b").unwrap();
+        let mut tcl = Interpreter::new(RecordingContext::default());
+        tcl.eval(script).unwrap();
+        assert_eq!(
+            *tcl.context().written.borrow(),
+            vec!["stdout: a\n", "stdout: b\n"]
+        );
+
+        let script = parser::parse("puts a\nputs b").unwrap();
+        let mut tcl = Interpreter::new(RecordingContext {
+            crlf: true,
+            ..RecordingContext::default()
+        });
+        tcl.eval(script).unwrap();
+        assert_eq!(
+            *tcl.context().written.borrow(),
+            vec!["stdout: a\r\n", "stdout: b\r\n"]
+        );
+    }
+
+    #[test]
+    fn test_puts_and_flush_through_context() {
+        let script = parser::parse("puts \"hello world\"\nflush stdout\nputs again").unwrap();
+        let mut tcl = Interpreter::new(RecordingContext::default());
+        assert_eq!(tcl.eval(script), Ok(String::new()));
+        assert_eq!(
+            *tcl.context().written.borrow(),
+            vec!["stdout: hello world\n", "stdout: again\n"]
+        );
+        assert_eq!(*tcl.context().flushed.borrow(), vec!["stdout"]);
+
+        let script = parser::parse("flush nowhere").unwrap();
+        assert_eq!(
+            tcl.eval(script),
+            Err(Error::UnknownChannel {
+                channel: "nowhere".to_string()
+            })
+        );
+        let script = parser::parse("flush").unwrap();
+        assert!(tcl.eval(script).is_err());
+    }
+
+    #[test]
+    fn test_puts_arguments() {
+        let script =
+            parser::parse("puts a\nputs -nonewline b\nputs stderr c\nputs -nonewline stderr d")
+                .unwrap();
+        let mut tcl = Interpreter::new(RecordingContext::default());
+        assert_eq!(tcl.eval(script), Ok(String::new()));
+        assert_eq!(
+            *tcl.context().written.borrow(),
+            vec!["stdout: a\n", "stdout: b", "stderr: c\n", "stderr: d"]
+        );
+
+        let script = parser::parse("puts -nonewline stdout a b").unwrap();
+        assert_eq!(
+            tcl.eval(script),
+            Err(Error::WrongArgs {
+                cmd: "puts".to_string(),
+                min: 1,
+                max: Some(3),
+                received: 4,
+            })
+        );
+        let script = parser::parse("puts stdout a b").unwrap();
+        assert!(tcl.eval(script).is_err());
+        let script = parser::parse("puts").unwrap();
+        assert!(tcl.eval(script).is_err());
+        assert_eq!(tcl.context().written.borrow().len(), 4);
+    }
+
+    #[test]
+    fn test_safe_interpreter() {
+        let script = parser::parse("after 10").unwrap();
+        let mut tcl = Interpreter::new_safe(RecordingContext::default());
+        assert_eq!(
+            tcl.eval(script),
+            Err(Error::NotPermitted {
+                cmd: "after".to_string()
+            })
+        );
+        assert!(tcl.context().slept.borrow().is_empty());
+
+        tcl.alias("wait", "after", vec!["10"]);
+        let script = parser::parse("wait").unwrap();
+        assert!(tcl.eval(script).is_err());
+
+        let mut tcl = Interpreter::new_safe(TestContext);
+        let script = parser::parse("exec rm -rf /").unwrap();
+        assert_eq!(
+            tcl.eval(script),
+            Err(Error::NotPermitted {
+                cmd: "exec".to_string()
+            })
+        );
+        let script = parser::parse("set a [+ 1 2]\nget a").unwrap();
+        assert_eq!(tcl.eval(script), Ok("3".to_string()));
+    }
+
+    #[cfg(feature = "graphemes")]
+    #[test]
+    fn test_context_graphemes() {
+        let script = parser::parse("string length \"e\u{301}\"").unwrap();
+        assert_eq!(
+            Interpreter::new(RecordingContext {
+                graphemes: true,
+                ..RecordingContext::default()
+            })
+            .eval(script),
+            Ok("1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_safe_interpreter_file() {
+        for subcommand in &["exists", "isdirectory", "isfile", "mtime", "size"] {
+            let source = format!("file {} /etc/passwd", subcommand);
+            let script = parser::parse(&source).unwrap();
+            assert_eq!(
+                Interpreter::new_safe(RecordingContext::default()).eval(script),
+                Err(Error::NotPermitted {
+                    cmd: format!("file {}", subcommand)
+                })
+            );
+        }
+
+        let script = parser::parse("file tail /etc/passwd").unwrap();
+        assert_eq!(
+            Interpreter::new_safe(RecordingContext::default()).eval(script),
+            Ok("passwd".to_string())
+        );
+    }
+
+    #[test]
+    fn test_buffered_output() {
+        let script = parser::parse("puts a\nputs b\nputs stderr c\nputs d").unwrap();
+        let mut tcl = Interpreter::new(RecordingContext::default());
+        tcl.set_buffered(true).unwrap();
+        assert_eq!(tcl.eval(script), Ok(String::new()));
+        assert_eq!(
+            *tcl.context().written.borrow(),
+            vec!["stdout: a\nb\n", "stderr: c\n", "stdout: d\n"]
+        );
+
+        tcl.context().written.borrow_mut().clear();
+        let script = parser::parse("puts e\nputs f\nflush stdout\nputs g").unwrap();
+        tcl.eval(script).unwrap();
+        assert_eq!(
+            *tcl.context().written.borrow(),
+            vec!["stdout: e\nf\n", "stdout: g\n"]
+        );
+        assert_eq!(*tcl.context().flushed.borrow(), vec!["stdout"]);
+
+        // Output before an error is still written
+        tcl.context().written.borrow_mut().clear();
+        let script = parser::parse("puts h\nnope\nputs i").unwrap();
+        assert!(tcl.eval(script).is_err());
+        assert_eq!(*tcl.context().written.borrow(), vec!["stdout: h\n"]);
+
+        // Unknown channels are reported by `puts`, not when the output is written
+        tcl.context().written.borrow_mut().clear();
+        let script = parser::parse("puts j\nputs nowhere x\nputs k").unwrap();
+        assert_eq!(
+            tcl.eval(script),
+            Err(Error::UnknownChannel {
+                channel: "nowhere".to_string()
+            })
+        );
+        let script = parser::parse("puts ok").unwrap();
+        assert_eq!(tcl.eval(script), Ok(String::new()));
+        assert_eq!(
+            *tcl.context().written.borrow(),
+            vec!["stdout: j\n", "stdout: ok\n"]
+        );
+    }
+
+    #[test]
+    fn test_capture_output() {
+        let script =
+            parser::parse("puts a\nputs -nonewline b\nputs stderr c\nflush stdout").unwrap();
+        let mut tcl = Interpreter::new(RecordingContext::default());
+        tcl.set_capture(true).unwrap();
+        assert_eq!(tcl.eval(script), Ok(String::new()));
+        assert_eq!(tcl.take_output(), "a\nbc\n");
+        assert_eq!(tcl.take_output(), "");
+        assert!(tcl.context().written.borrow().is_empty());
+
+        let script = parser::parse("puts d").unwrap();
+        tcl.eval(script).unwrap();
+        tcl.set_capture(false).unwrap();
+        assert_eq!(tcl.take_output(), "");
+        let script = parser::parse("puts e").unwrap();
+        tcl.eval(script).unwrap();
+        assert_eq!(*tcl.context().written.borrow(), vec!["stdout: e\n"]);
+    }
+
+    #[test]
+    fn test_capture_keeps_buffering() {
+        let mut tcl = Interpreter::new(RecordingContext::default());
+        tcl.set_buffered(true).unwrap();
+        tcl.set_capture(true).unwrap();
+        tcl.eval(parser::parse("puts a").unwrap()).unwrap();
+        assert_eq!(tcl.take_output(), "a\n");
+        tcl.set_capture(false).unwrap();
+
+        let script = parser::parse("puts b\nputs c\nflush stdout").unwrap();
+        tcl.eval(script).unwrap();
+        assert_eq!(*tcl.context().written.borrow(), vec!["stdout: b\nc\n"]);
+
+        // Turning buffering off while capturing applies once capturing stops
+        tcl.set_capture(true).unwrap();
+        tcl.set_buffered(false).unwrap();
+        tcl.set_capture(false).unwrap();
+        tcl.context().written.borrow_mut().clear();
+        tcl.eval(parser::parse("puts d\nputs e").unwrap()).unwrap();
+        assert_eq!(
+            *tcl.context().written.borrow(),
+            vec!["stdout: d\n", "stdout: e\n"]
+        );
+    }
+
+    #[test]
+    fn test_capture_unknown_channel() {
+        let script = parser::parse("puts a\nputs nowhere x").unwrap();
+        let mut tcl = Interpreter::new(RecordingContext::default());
+        tcl.set_capture(true).unwrap();
+        assert_eq!(
+            tcl.eval(script),
+            Err(Error::UnknownChannel {
+                channel: "nowhere".to_string()
+            })
+        );
+        assert_eq!(tcl.take_output(), "a\n");
+    }
+
+    #[test]
+    fn test_after_sleeps_through_context() {
+        let script = parser::parse("after 25\nafter 1000").unwrap();
+        let mut tcl = Interpreter::new(RecordingContext::default());
+        assert_eq!(tcl.eval(script), Ok(String::new()));
+        assert_eq!(
+            *tcl.context().slept.borrow(),
+            vec![Duration::from_millis(25), Duration::from_secs(1)]
+        );
+
+        let script = parser::parse("after soon").unwrap();
+        assert!(tcl.eval(script).is_err());
+    }
+
     //    #[test]
     //    fn test_substitute_inline_variable() {
     //        let mut variables = HashMap::new();
@@ -315,8 +1904,8 @@ mod tests {
     // #[test]
     fn _bench_unescape() {
         println!(
-            "unescape:        {}",
-            bench(|| unescape("This is some sample text without escapes"))
+            "substitute:      {}",
+            bench(|| substitute_backslashes("This is some sample text without escapes"))
         );
         println!(
             "unescape_no_cow: {}",
@@ -324,8 +1913,8 @@ mod tests {
         );
 
         println!(
-            "unescape esc:        {}",
-            bench(|| unescape("This is some sample \\\"text\\\" with\\nescapes"))
+            "substitute esc:       {}",
+            bench(|| substitute_backslashes("This is some sample \\\"text\\\" with\\nescapes"))
         );
         println!(
             "unescape_no_cow esc: {}",
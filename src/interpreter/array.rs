@@ -0,0 +1,206 @@
+use std::borrow::Cow;
+
+use super::{build_list, Command, Context, Error, EvalResult, Variables};
+
+/// The `array` command and its subcommands.
+///
+/// The elements of an array `name` are stored as variables called `name(key)`. Keys are listed
+/// in sorted order so results do not depend on the order of the variables map.
+pub struct Array;
+
+impl<'a> Command<'a> for Array {
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(2)
+    }
+
+    fn eval(&self, variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("array", &args)?;
+        let (subcommand, name) = (&args[0], &args[1]);
+
+        let elements = elements(variables, name);
+        match subcommand.as_ref() {
+            "exists" => Ok(if elements.is_empty() { "0" } else { "1" }.to_string()),
+            "get" => build_list(elements.iter().flat_map(|&(key, value)| vec![key, value])),
+            "names" => build_list(elements.iter().map(|&(key, _)| key)),
+            "size" => Ok(elements.len().to_string()),
+            _ => Err(Error::Malformed {
+                cmd: "array",
+                message: "unknown subcommand",
+                got: vec![subcommand.to_string()],
+            }),
+        }
+    }
+}
+
+/// Prints the elements of an array as `name(key) = value` lines on stdout, sorted by key:
+/// `parray name`.
+pub struct Parray<'c, C>(pub &'c C);
+
+impl<'a, 'c, C> Command<'a> for Parray<'c, C>
+where
+    C: Context<'a>,
+{
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(1)
+    }
+
+    fn eval(&self, variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("parray", &args)?;
+        let name = &args[0];
+        let elements = elements(variables, name);
+        if elements.is_empty() {
+            return Err(Error::Malformed {
+                cmd: "parray",
+                message: "not an array",
+                got: vec![name.to_string()],
+            });
+        }
+
+        let mut text = String::new();
+        for (key, value) in elements {
+            text.push_str(&format!(
+                "{}({}) = {}{}",
+                name,
+                key,
+                value,
+                self.0.line_ending()
+            ));
+        }
+        self.0.write("stdout", &text)?;
+
+        Ok(String::new())
+    }
+}
+
+/// The keys and values of the elements of the array `name`, sorted by key.
+pub(crate) fn elements<'v>(variables: &'v Variables, name: &str) -> Vec<(&'v str, &'v str)> {
+    let mut elements = variables
+        .iter()
+        .filter_map(|(variable, value)| {
+            let key = variable
+                .strip_prefix(name)?
+                .strip_prefix('(')?
+                .strip_suffix(')')?;
+            Some((key, value.as_str()))
+        })
+        .collect::<Vec<_>>();
+    elements.sort_unstable();
+    elements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::output::OutputBuffer;
+    use crate::interpreter::to_args;
+    use std::collections::HashMap;
+
+    fn array(variables: &mut Variables, args: &[&str]) -> EvalResult {
+        Array.eval(variables, to_args(args))
+    }
+
+    fn colours() -> Variables {
+        let mut variables = HashMap::new();
+        for (name, value) in &[
+            ("colour(red)", "f00"),
+            ("colour(green)", "0f0"),
+            ("colour(blue)", "00f"),
+            ("colour(dark grey)", "333"),
+            ("colours", "3"),
+            ("other(red)", "1"),
+        ] {
+            variables.insert(name.to_string(), value.to_string());
+        }
+        variables
+    }
+
+    #[test]
+    fn test_unknown_subcommand() {
+        let mut variables = colours();
+        assert!(array(&mut variables, &[]).is_err());
+        assert!(array(&mut variables, &["nope", "colour"]).is_err());
+        assert_eq!(
+            array(&mut variables, &["names"]),
+            Err(Error::WrongArgs {
+                cmd: "array".to_string(),
+                min: 2,
+                max: Some(2),
+                received: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn test_names_sorted() {
+        let mut variables = colours();
+        for _ in 0..10 {
+            assert_eq!(
+                array(&mut variables, &["names", "colour"]),
+                Ok("blue {dark grey} green red".to_string())
+            );
+        }
+        assert_eq!(array(&mut variables, &["names", "none"]), Ok(String::new()));
+    }
+
+    #[test]
+    fn test_get() {
+        let mut variables = colours();
+        assert_eq!(
+            array(&mut variables, &["get", "colour"]),
+            Ok("blue 00f {dark grey} 333 green 0f0 red f00".to_string())
+        );
+    }
+
+    #[test]
+    fn test_size_and_exists() {
+        let mut variables = colours();
+        assert_eq!(
+            array(&mut variables, &["size", "colour"]),
+            Ok("4".to_string())
+        );
+        assert_eq!(
+            array(&mut variables, &["size", "none"]),
+            Ok("0".to_string())
+        );
+        assert_eq!(
+            array(&mut variables, &["exists", "other"]),
+            Ok("1".to_string())
+        );
+        assert_eq!(
+            array(&mut variables, &["exists", "colours"]),
+            Ok("0".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parray() {
+        let mut variables = colours();
+        let output = OutputBuffer::new("\n", true);
+        let parray = Parray(&output);
+        assert_eq!(
+            parray.call("parray", &mut variables, vec![Cow::from("colour")]),
+            Ok(String::new())
+        );
+        assert_eq!(
+            output.take(),
+            vec![(
+                "stdout".to_string(),
+                "colour(blue) = 00f\ncolour(dark grey) = 333\ncolour(green) = 0f0\ncolour(red) = f00\n"
+                    .to_string()
+            )]
+        );
+        assert!(parray
+            .call("parray", &mut variables, vec![Cow::from("colours")])
+            .is_err());
+        assert!(parray.call("parray", &mut variables, Vec::new()).is_err());
+        assert!(output.take().is_empty());
+    }
+}
@@ -1,35 +1,946 @@
 use std::borrow::Cow;
+use std::iter;
+use std::time::Duration;
 
-use super::{Error, EvalResult, Variables};
+use super::{
+    build_list_with_limit, check_length, parse_tcl_int, split_list, Context, Error, EvalResult,
+    Variables, DEFAULT_LIST_LIMIT,
+};
 
 pub trait Command<'a> {
+    /// Evaluate the command. `call` checks `args` against `min_args` and `max_args` first, but
+    /// `eval` may also be called directly, so it must not assume the number of arguments is in
+    /// range. Commands can use `check_args` for this.
     fn eval(&self, variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult;
+
+    /// The minimum number of arguments accepted by the command.
+    fn min_args(&self) -> usize {
+        0
+    }
+
+    /// The maximum number of arguments accepted by the command, or `None` if there is no limit.
+    fn max_args(&self) -> Option<usize> {
+        None
+    }
+
+    /// Return `Error::WrongArgs` for the command invoked as `name` if the number of arguments is
+    /// outside `min_args` to `max_args`.
+    fn check_args(&self, name: &str, args: &[Cow<'a, str>]) -> Result<(), Error> {
+        let (min, max) = (self.min_args(), self.max_args());
+        if args.len() < min || max.is_some_and(|max| args.len() > max) {
+            return Err(Error::WrongArgs {
+                cmd: name.to_string(),
+                min,
+                max,
+                received: args.len(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Evaluate the command invoked as `name`, returning `Error::WrongArgs` without calling
+    /// `eval` if the number of arguments is outside `min_args` to `max_args`.
+    fn call(&self, name: &str, variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args(name, &args)?;
+        self.eval(variables, args)
+    }
 }
 
 pub struct Set;
 
-pub struct Puts;
+/// Writes a string followed by `Context::line_ending` to a channel, stdout by default, using
+/// `Context::write`. The line ending is omitted with `-nonewline`.
+pub struct Puts<'c, C>(pub &'c C);
+
+/// Flushes a channel using `Context::flush`.
+pub struct Flush<'c, C>(pub &'c C);
+
+/// Blocks for the given number of milliseconds using `Context::sleep`.
+pub struct After<'c, C>(pub &'c C);
 
 impl<'a> Command<'a> for Set {
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(2)
+    }
+
     fn eval(&self, variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
-        if args.len() != 2 {
-            return Err(Error::Arity {
-                cmd: "set",
-                expected: 2,
-                received: args.len(),
-            });
+        self.check_args("set", &args)?;
+        variables.insert(args[0].to_string(), args[1].to_string());
+
+        Ok(String::new())
+    }
+}
+
+impl<'a, 'c, C> Command<'a> for Puts<'c, C>
+where
+    C: Context<'a>,
+{
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(3)
+    }
+
+    fn eval(&self, _variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("puts", &args)?;
+        let (newline, channel, text) = match args.as_slice() {
+            [text] => (true, "stdout", text),
+            [flag, text] if flag == "-nonewline" => (false, "stdout", text),
+            [channel, text] => (true, channel.as_ref(), text),
+            [flag, channel, text] if flag == "-nonewline" => (false, channel.as_ref(), text),
+            _ => {
+                return Err(Error::Malformed {
+                    cmd: "puts",
+                    message: "expected ?-nonewline? ?channelId? string",
+                    got: args.iter().map(|arg| arg.to_string()).collect(),
+                })
+            }
+        };
+
+        if newline {
+            self.0
+                .write(channel, &format!("{}{}", text, self.0.line_ending()))?;
+        } else {
+            self.0.write(channel, text)?;
         }
 
-        variables.insert(args[0].to_string(), args[1].to_string());
+        Ok(String::new())
+    }
+}
+
+impl<'a, 'c, C> Command<'a> for Flush<'c, C>
+where
+    C: Context<'a>,
+{
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(1)
+    }
+
+    fn eval(&self, _variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("flush", &args)?;
+        self.0.flush(&args[0])?;
 
         Ok(String::new())
     }
 }
 
-impl<'a> Command<'a> for Puts {
+impl<'a, 'c, C> Command<'a> for After<'c, C>
+where
+    C: Context<'a>,
+{
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(1)
+    }
+
     fn eval(&self, _variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
-        println!("{}", args.join(" "));
+        self.check_args("after", &args)?;
+        let ms = parse_tcl_int(&args[0])?;
+        if ms < 0 {
+            return Err(Error::Conversion {
+                value: args[0].to_string(),
+                message: "expected non-negative integer",
+            });
+        }
+        self.0.sleep(Duration::from_millis(ms as u64));
 
         Ok(String::new())
     }
 }
+
+/// Appends its values to a variable and returns the new value.
+///
+/// With no values the current value is returned unchanged. An undefined variable is created.
+pub struct Append;
+
+impl<'a> Command<'a> for Append {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn eval(&self, variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("append", &args)?;
+        let value = variables.entry(args[0].to_string()).or_default();
+        for arg in &args[1..] {
+            value.push_str(arg);
+        }
+
+        Ok(value.clone())
+    }
+}
+
+/// Adds an integer, 1 by default, to a variable and returns the new value.
+///
+/// An undefined variable is treated as 0. Array elements are named `array(key)` like any other
+/// variable.
+pub struct Incr;
+
+impl<'a> Command<'a> for Incr {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(2)
+    }
+
+    fn eval(&self, variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("incr", &args)?;
+        let increment = args
+            .get(1)
+            .map_or(Ok(1), |increment| parse_tcl_int(increment))?;
+        let value = match variables.get(args[0].as_ref()) {
+            Some(value) => parse_tcl_int(value)?,
+            None => 0,
+        };
+        let value = value
+            .checked_add(increment)
+            .ok_or_else(|| Error::Conversion {
+                value: value.to_string(),
+                message: "integer value too large",
+            })?
+            .to_string();
+
+        variables.insert(args[0].to_string(), value.clone());
+        Ok(value)
+    }
+}
+
+/// Splits a string into a list.
+///
+/// Each character of the optional second argument is a separator (by default any Unicode
+/// whitespace). Adjacent separators produce empty elements. An empty set of
+/// separators splits the string into its characters. The list is limited to `limit` bytes and
+/// elements.
+pub struct Split {
+    pub limit: usize,
+}
+
+impl Default for Split {
+    fn default() -> Self {
+        Split {
+            limit: DEFAULT_LIST_LIMIT,
+        }
+    }
+}
+
+impl<'a> Command<'a> for Split {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(2)
+    }
+
+    fn eval(&self, _variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("split", &args)?;
+        let string = &args[0];
+
+        let elements: Vec<&str> = match args.get(1) {
+            _ if string.is_empty() => return Ok(String::new()),
+            None => string.split(char::is_whitespace).collect(),
+            Some(separators) if separators.is_empty() => string
+                .char_indices()
+                .map(|(i, c)| &string[i..i + c.len_utf8()])
+                .collect(),
+            Some(separators) => string.split(|c| separators.contains(c)).collect(),
+        };
+        check_length(elements.len(), self.limit)?;
+
+        build_list_with_limit(elements, self.limit)
+    }
+}
+
+/// Converts between strings and binary data.
+///
+/// Tcl strings are Rust `String`s so binary data is represented by mapping each byte to the
+/// character with the same code point (U+0000 to U+00FF, as in ISO 8859-1). The result of
+/// `binary format` uses this representation and `binary scan` expects it.
+pub struct Binary;
+
+impl<'a> Command<'a> for Binary {
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn eval(&self, variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("binary", &args)?;
+        match args.first().map(|arg| arg.as_ref()) {
+            Some("format") => binary_format(&args[1], &args[2..]),
+            Some("scan") if args.len() >= 3 => {
+                binary_scan(variables, &args[1], &args[2], &args[3..])
+            }
+            _ => Err(Error::Malformed {
+                cmd: "binary",
+                message: "expected 'format formatString ?arg ...?' or 'scan value formatString ?varName ...?'",
+                got: to_strings(&args),
+            }),
+        }
+    }
+}
+
+/// Converts strings to and from binary data in a particular encoding.
+///
+/// Binary data uses the same one character per byte representation as `Binary`.
+pub struct Encoding;
+
+const ENCODINGS: &[&str] = &["iso8859-1", "utf-8"];
+
+impl<'a> Command<'a> for Encoding {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(3)
+    }
+
+    fn eval(&self, _variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("encoding", &args)?;
+        let (subcommand, encoding, data) = match args.as_slice() {
+            [subcommand] if subcommand == "names" => return Ok(ENCODINGS.join(" ")),
+            [subcommand, data] => (subcommand, "utf-8", data),
+            [subcommand, encoding, data] => (subcommand, encoding.as_ref(), data),
+            _ => {
+                return Err(Error::Malformed {
+                    cmd: "encoding",
+                    message: "expected 'names' or 'convertto|convertfrom ?encoding? data'",
+                    got: to_strings(&args),
+                })
+            }
+        };
+
+        match (subcommand.as_ref(), encoding) {
+            ("convertto", "utf-8") => Ok(from_bytes(data.as_bytes())),
+            ("convertto", "iso8859-1") => Ok(data
+                .chars()
+                .map(|c| if (c as u32) <= 0xFF { c } else { '?' })
+                .collect()),
+            ("convertfrom", "utf-8") => {
+                String::from_utf8(to_bytes(data)?).map_err(|_| Error::Conversion {
+                    value: data.to_string(),
+                    message: "invalid utf-8",
+                })
+            }
+            ("convertfrom", "iso8859-1") => to_bytes(data).map(|bytes| from_bytes(&bytes)),
+            ("convertto", _) | ("convertfrom", _) => Err(Error::Malformed {
+                cmd: "encoding",
+                message: "unknown encoding",
+                got: vec![encoding.to_string()],
+            }),
+            _ => Err(Error::Malformed {
+                cmd: "encoding",
+                message: "expected 'names', 'convertto' or 'convertfrom' subcommand",
+                got: to_strings(&args),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Count {
+    Default,
+    Exact(usize),
+    All,
+}
+
+fn parse_binary_format(format: &str) -> Result<Vec<(char, Count)>, Error> {
+    let mut specs = Vec::new();
+    let mut chars = format.chars().filter(|c| !c.is_whitespace()).peekable();
+
+    while let Some(code) = chars.next() {
+        match code {
+            'a' | 'A' | 'c' | 's' | 'S' | 'i' | 'I' => {}
+            _ => {
+                return Err(Error::Malformed {
+                    cmd: "binary",
+                    message: "unsupported format code",
+                    got: vec![code.to_string()],
+                })
+            }
+        }
+
+        let count = if chars.peek() == Some(&'*') {
+            chars.next();
+            Count::All
+        } else {
+            let mut digits = String::new();
+            while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                digits.push(*c);
+                chars.next();
+            }
+
+            if digits.is_empty() {
+                Count::Default
+            } else {
+                Count::Exact(digits.parse().map_err(|_| Error::Conversion {
+                    value: digits,
+                    message: "count is too large",
+                })?)
+            }
+        };
+
+        specs.push((code, count));
+    }
+
+    Ok(specs)
+}
+
+/// The maximum length in bytes of the binary string produced by `binary format`.
+const BINARY_SIZE_LIMIT: usize = 64 * 1024 * 1024;
+
+fn count_too_large(count: usize) -> Error {
+    Error::Conversion {
+        value: count.to_string(),
+        message: "count is too large",
+    }
+}
+
+fn int_width(code: char) -> usize {
+    match code {
+        'c' => 1,
+        's' | 'S' => 2,
+        _ => 4,
+    }
+}
+
+fn binary_format<'a>(format: &str, args: &[Cow<'a, str>]) -> EvalResult {
+    let mut bytes = Vec::new();
+    let mut values = args.iter();
+
+    for (code, count) in parse_binary_format(format)? {
+        let value = values.next().ok_or_else(|| Error::Malformed {
+            cmd: "binary",
+            message: "not enough arguments for all format specifiers",
+            got: to_strings(args),
+        })?;
+
+        match code {
+            'a' | 'A' => {
+                let pad = if code == 'a' { 0 } else { b' ' };
+                // Like Tcl only the low 8 bits of each character are stored
+                let data = value.chars().map(|c| c as u32 as u8);
+                let len = match count {
+                    Count::Default => 1,
+                    Count::Exact(len) => len,
+                    Count::All => value.chars().count(),
+                };
+                if bytes.len().saturating_add(len) > BINARY_SIZE_LIMIT {
+                    return Err(count_too_large(len));
+                }
+                bytes.extend(data.chain(iter::repeat(pad)).take(len));
+            }
+            _ => {
+                let items = match count {
+                    Count::Default => vec![Cow::from(value.as_ref())],
+                    Count::Exact(len) => {
+                        let mut items = split_list(value)?;
+                        if items.len() < len {
+                            return Err(Error::Malformed {
+                                cmd: "binary",
+                                message: "number of elements in list does not match count",
+                                got: vec![value.to_string()],
+                            });
+                        }
+                        items.truncate(len);
+                        items
+                    }
+                    Count::All => split_list(value)?,
+                };
+                let size = items.len().saturating_mul(int_width(code));
+                if bytes.len().saturating_add(size) > BINARY_SIZE_LIMIT {
+                    return Err(count_too_large(items.len()));
+                }
+
+                for item in items {
+                    let number = parse_tcl_int(&item)?;
+
+                    match code {
+                        'c' => bytes.push(number as u8),
+                        's' => bytes.extend_from_slice(&(number as u16).to_le_bytes()),
+                        'S' => bytes.extend_from_slice(&(number as u16).to_be_bytes()),
+                        'i' => bytes.extend_from_slice(&(number as u32).to_le_bytes()),
+                        _ => bytes.extend_from_slice(&(number as u32).to_be_bytes()),
+                    }
+                }
+            }
+        }
+    }
+
+    if values.next().is_some() {
+        return Err(Error::Malformed {
+            cmd: "binary",
+            message: "too many arguments for format specifiers",
+            got: to_strings(args),
+        });
+    }
+
+    Ok(from_bytes(&bytes))
+}
+
+fn binary_scan<'a>(
+    variables: &mut Variables,
+    value: &str,
+    format: &str,
+    names: &[Cow<'a, str>],
+) -> EvalResult {
+    let bytes = to_bytes(value)?;
+    let specs = parse_binary_format(format)?;
+
+    if specs.len() != names.len() {
+        // `binary scan value format name...`
+        return Err(Error::WrongArgs {
+            cmd: "binary".to_string(),
+            min: specs.len() + 3,
+            max: Some(specs.len() + 3),
+            received: names.len() + 3,
+        });
+    }
+
+    let mut pos = 0;
+    let mut converted = 0;
+
+    for ((code, count), name) in specs.into_iter().zip(names) {
+        let remaining = bytes.len() - pos;
+        let result = match code {
+            'a' | 'A' => {
+                let len = match count {
+                    Count::Default => 1,
+                    Count::Exact(len) => len,
+                    Count::All => remaining,
+                };
+                if len > remaining {
+                    break;
+                }
+
+                let mut field = &bytes[pos..pos + len];
+                pos += len;
+                if code == 'A' {
+                    while let Some((&last, rest)) = field.split_last() {
+                        if last != b' ' && last != 0 {
+                            break;
+                        }
+                        field = rest;
+                    }
+                }
+                from_bytes(field)
+            }
+            _ => {
+                let width = int_width(code);
+                let len = match count {
+                    Count::Default => 1,
+                    Count::Exact(len) => len,
+                    Count::All => remaining / width,
+                };
+                let size = len.checked_mul(width).ok_or_else(|| count_too_large(len))?;
+                if size > remaining {
+                    break;
+                }
+
+                let numbers = bytes[pos..pos + size]
+                    .chunks(width)
+                    .map(|b| match code {
+                        'c' => i64::from(b[0] as i8),
+                        's' => i64::from(i16::from_le_bytes([b[0], b[1]])),
+                        'S' => i64::from(i16::from_be_bytes([b[0], b[1]])),
+                        'i' => i64::from(i32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                        _ => i64::from(i32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+                    })
+                    .map(|number| number.to_string())
+                    .collect::<Vec<_>>();
+                pos += size;
+                numbers.join(" ")
+            }
+        };
+
+        variables.insert(name.to_string(), result);
+        converted += 1;
+    }
+
+    Ok(converted.to_string())
+}
+
+/// Converts binary data represented as a string back into bytes.
+fn to_bytes(value: &str) -> Result<Vec<u8>, Error> {
+    value
+        .chars()
+        .map(|c| {
+            if (c as u32) <= 0xFF {
+                Ok(c as u32 as u8)
+            } else {
+                Err(Error::Conversion {
+                    value: value.to_string(),
+                    message: "binary data must only contain characters U+0000 to U+00FF",
+                })
+            }
+        })
+        .collect()
+}
+
+/// Represents bytes as a string, one character per byte.
+fn from_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| char::from(b)).collect()
+}
+
+fn to_strings<'a>(args: &[Cow<'a, str>]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::to_args;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_call_arity() {
+        let mut variables = HashMap::new();
+        let error = Set
+            .call("set", &mut variables, to_args(&["a"]))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            Error::WrongArgs {
+                cmd: "set".to_string(),
+                min: 2,
+                max: Some(2),
+                received: 1,
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Expected 2 arguments to 'set', received 1"
+        );
+        assert_eq!(
+            Split::default()
+                .call("split", &mut variables, to_args(&["a", "b", "c"]))
+                .unwrap_err()
+                .to_string(),
+            "Expected 1 to 2 arguments to 'split', received 3"
+        );
+        assert!(variables.is_empty());
+
+        assert_eq!(
+            Set.call("set", &mut variables, to_args(&["a", "1"])),
+            Ok(String::new())
+        );
+        assert_eq!(variables.get("a").map(String::as_str), Some("1"));
+    }
+
+    #[test]
+    fn test_eval_checks_arity() {
+        let mut variables = HashMap::new();
+        assert_eq!(
+            Set.eval(&mut variables, vec![]),
+            Err(Error::WrongArgs {
+                cmd: "set".to_string(),
+                min: 2,
+                max: Some(2),
+                received: 0,
+            })
+        );
+        assert!(Incr.eval(&mut variables, vec![]).is_err());
+        assert!(Append.eval(&mut variables, vec![]).is_err());
+        assert!(variables.is_empty());
+    }
+
+    #[test]
+    fn test_append() {
+        let mut variables = HashMap::new();
+        assert_eq!(
+            Append.call("append", &mut variables, to_args(&["x"])),
+            Ok(String::new())
+        );
+        assert_eq!(variables.get("x").map(String::as_str), Some(""));
+        assert_eq!(
+            Append.call("append", &mut variables, to_args(&["x", "ab", "c"])),
+            Ok("abc".to_string())
+        );
+        assert_eq!(
+            Append.call("append", &mut variables, to_args(&["x", " d"])),
+            Ok("abc d".to_string())
+        );
+        assert_eq!(
+            Append.call("append", &mut variables, to_args(&["x"])),
+            Ok("abc d".to_string())
+        );
+        assert_eq!(variables.get("x").map(String::as_str), Some("abc d"));
+        assert!(Append.call("append", &mut variables, to_args(&[])).is_err());
+    }
+
+    #[test]
+    fn test_incr() {
+        let mut variables = HashMap::new();
+        let mut incr = |values: &[&'static str]| Incr.call("incr", &mut variables, to_args(values));
+        assert_eq!(incr(&["x"]), Ok("1".to_string()));
+        assert_eq!(incr(&["x"]), Ok("2".to_string()));
+        assert_eq!(incr(&["x", "-5"]), Ok("-3".to_string()));
+        assert_eq!(incr(&["x", "0x10"]), Ok("13".to_string()));
+        assert!(incr(&["x", "1.5"]).is_err());
+        assert!(incr(&[]).is_err());
+        assert_eq!(incr(&["x"]), Ok("14".to_string()));
+
+        variables.insert("s".to_string(), "abc".to_string());
+        variables.insert("big".to_string(), i64::MAX.to_string());
+        assert!(Incr.call("incr", &mut variables, to_args(&["s"])).is_err());
+        assert!(Incr
+            .call("incr", &mut variables, to_args(&["big"]))
+            .is_err());
+        assert_eq!(variables.get("s").map(String::as_str), Some("abc"));
+    }
+
+    #[test]
+    fn test_incr_rejects_booleans() {
+        let not_integer = |value: &str| Error::Conversion {
+            value: value.to_string(),
+            message: "expected integer",
+        };
+        let mut variables = HashMap::new();
+        variables.insert("x".to_string(), "true".to_string());
+        assert_eq!(
+            Incr.call("incr", &mut variables, to_args(&["x"])),
+            Err(not_integer("true"))
+        );
+        assert_eq!(
+            Incr.call("incr", &mut variables, to_args(&["y", "yes"])),
+            Err(not_integer("yes"))
+        );
+        assert_eq!(parse_tcl_int("true"), Err(not_integer("true")));
+        assert_eq!(variables.get("x").map(String::as_str), Some("true"));
+        assert!(!variables.contains_key("y"));
+    }
+
+    #[test]
+    fn test_incr_array_element() {
+        let mut variables = HashMap::new();
+        assert_eq!(
+            Incr.call("incr", &mut variables, to_args(&["count(a)"])),
+            Ok("1".to_string())
+        );
+        variables.insert("count(b)".to_string(), "41".to_string());
+        assert_eq!(
+            Incr.call("incr", &mut variables, to_args(&["count(b)"])),
+            Ok("42".to_string())
+        );
+        assert_eq!(variables.get("count(a)").map(String::as_str), Some("1"));
+        assert_eq!(variables.get("count(b)").map(String::as_str), Some("42"));
+    }
+
+    #[test]
+    fn test_split_limit() {
+        let mut variables = HashMap::new();
+        assert_eq!(
+            Split { limit: 7 }.eval(&mut variables, to_args(&["a b c d"])),
+            Ok("a b c d".to_string())
+        );
+        assert_eq!(
+            Split { limit: 6 }.eval(&mut variables, to_args(&["a b c d"])),
+            Err(Error::ListTooLong { limit: 6 })
+        );
+        assert_eq!(
+            Split { limit: 3 }.eval(&mut variables, to_args(&["abcd", ""])),
+            Err(Error::ListTooLong { limit: 3 })
+        );
+    }
+
+    #[test]
+    fn test_split() {
+        let mut variables = HashMap::new();
+        assert_eq!(
+            Split::default().eval(&mut variables, to_args(&["a::b", ":"])),
+            Ok("a {} b".to_string())
+        );
+        assert_eq!(
+            Split::default().eval(&mut variables, to_args(&["a:b;c", ";:"])),
+            Ok("a b c".to_string())
+        );
+        assert_eq!(
+            Split::default().eval(&mut variables, to_args(&["a\tb  c\n"])),
+            Ok("a b {} c {}".to_string())
+        );
+        assert_eq!(
+            Split::default().eval(&mut variables, to_args(&["a\u{a0}b\u{2028}c"])),
+            Ok("a b c".to_string())
+        );
+        assert_eq!(
+            Split::default().eval(&mut variables, to_args(&["a b", ""])),
+            Ok("a { } b".to_string())
+        );
+        assert_eq!(
+            Split::default().eval(&mut variables, to_args(&["a.b", "ab"])),
+            Ok("{} . {}".to_string())
+        );
+        assert_eq!(
+            Split::default().eval(&mut variables, to_args(&["", ":"])),
+            Ok(String::new())
+        );
+        assert!(Split::default()
+            .call("split", &mut variables, to_args(&[]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_binary_format() {
+        let mut variables = HashMap::new();
+        assert_eq!(
+            Binary.eval(&mut variables, to_args(&["format", "S", "258"])),
+            Ok("\u{1}\u{2}".to_string())
+        );
+        assert_eq!(
+            Binary.eval(
+                &mut variables,
+                to_args(&["format", "cc", "0x41", "0b1000010"])
+            ),
+            Ok("AB".to_string())
+        );
+        assert_eq!(
+            Binary.eval(&mut variables, to_args(&["format", "i", "-2"])),
+            Ok("\u{fe}\u{ff}\u{ff}\u{ff}".to_string())
+        );
+        assert_eq!(
+            Binary.eval(
+                &mut variables,
+                to_args(&["format", "a4A4c", "ab", "cd", "65"])
+            ),
+            Ok("ab\u{0}\u{0}cd  A".to_string())
+        );
+        assert!(Binary
+            .eval(&mut variables, to_args(&["format", "c3", "1 2"]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let mut variables = HashMap::new();
+        let data = Binary
+            .eval(
+                &mut variables,
+                to_args(&["format", "IsA*", "305419896", "-3", "end "]),
+            )
+            .unwrap();
+        assert_eq!(data.chars().count(), 10);
+
+        assert_eq!(
+            Binary.eval(
+                &mut variables,
+                to_args(&["scan", &data, "IsA*", "a", "b", "c"])
+            ),
+            Ok("3".to_string())
+        );
+        assert_eq!(variables["a"], "305419896");
+        assert_eq!(variables["b"], "-3");
+        assert_eq!(variables["c"], "end");
+    }
+
+    #[test]
+    fn test_binary_scan_short_data() {
+        let mut variables = HashMap::new();
+        assert_eq!(
+            Binary.eval(&mut variables, to_args(&["scan", "\u{1}", "ci", "a", "b"])),
+            Ok("1".to_string())
+        );
+        assert_eq!(variables["a"], "1");
+        assert_eq!(variables.get("b"), None);
+        assert!(Binary
+            .eval(&mut variables, to_args(&["scan", "\u{100}", "c", "a"]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_binary_counts_too_large() {
+        let mut variables = HashMap::new();
+        assert_eq!(
+            Binary.eval(
+                &mut variables,
+                to_args(&["scan", "abcd", "i4611686018427387904", "x"])
+            ),
+            Err(count_too_large(4611686018427387904))
+        );
+        assert_eq!(
+            Binary.eval(&mut variables, to_args(&["format", "a99999999999", "x"])),
+            Err(count_too_large(99999999999))
+        );
+        assert!(Binary
+            .eval(
+                &mut variables,
+                to_args(&["format", "a99999999999999999999", "x"])
+            )
+            .is_err());
+        assert_eq!(variables.get("x"), None);
+    }
+
+    #[test]
+    fn test_binary_format_list_arguments() {
+        let mut variables = HashMap::new();
+        assert_eq!(
+            Binary.eval(&mut variables, to_args(&["format", "c3", "1 {2} 3"])),
+            Ok("\u{1}\u{2}\u{3}".to_string())
+        );
+        assert_eq!(
+            Binary.eval(&mut variables, to_args(&["format", "c*", "{4}\n\"5\""])),
+            Ok("\u{4}\u{5}".to_string())
+        );
+        assert!(Binary
+            .eval(&mut variables, to_args(&["format", "c2", "{1 2"]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_encoding_round_trip() {
+        let mut variables = HashMap::new();
+        let latin1 = Encoding
+            .eval(&mut variables, to_args(&["convertto", "iso8859-1", "café"]))
+            .unwrap();
+        assert_eq!(latin1, "caf\u{e9}");
+        assert_eq!(
+            Encoding.eval(
+                &mut variables,
+                to_args(&["convertfrom", "iso8859-1", &latin1])
+            ),
+            Ok("café".to_string())
+        );
+
+        let utf8 = Encoding
+            .eval(&mut variables, to_args(&["convertto", "café"]))
+            .unwrap();
+        assert_eq!(utf8, "caf\u{c3}\u{a9}");
+        assert_eq!(
+            Encoding.eval(&mut variables, to_args(&["convertfrom", "utf-8", &utf8])),
+            Ok("café".to_string())
+        );
+    }
+
+    #[test]
+    fn test_encoding_errors() {
+        let mut variables = HashMap::new();
+        assert_eq!(
+            Encoding.eval(&mut variables, to_args(&["names"])),
+            Ok("iso8859-1 utf-8".to_string())
+        );
+        assert_eq!(
+            Encoding.eval(&mut variables, to_args(&["convertto", "iso8859-1", "π"])),
+            Ok("?".to_string())
+        );
+        assert!(Encoding
+            .eval(&mut variables, to_args(&["convertfrom", "\u{c3}"]))
+            .is_err());
+        assert!(Encoding
+            .eval(&mut variables, to_args(&["convertto", "ebcdic", "abc"]))
+            .is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use std::borrow::Cow;
+
+use super::{build_list, split_list, Command, Error, EvalResult, Variables};
+
+/// The `dict` command and its subcommands.
+///
+/// Dictionaries are lists with an even number of elements, alternating keys and values.
+pub struct Dict;
+
+impl<'a> Command<'a> for Dict {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn eval(&self, _variables: &mut Variables, mut args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("dict", &args)?;
+        let subcommand = args.remove(0);
+        match &*subcommand {
+            "merge" => merge(&args),
+            _ => Err(Error::Malformed {
+                cmd: "dict",
+                message: "unknown subcommand",
+                got: vec![subcommand.to_string()],
+            }),
+        }
+    }
+}
+
+/// Add the key/value pairs of a dictionary to `pairs`. A key that is already present has its value
+/// replaced but keeps its position.
+fn dict_pairs<'d>(
+    dict: &'d str,
+    pairs: &mut Vec<(Cow<'d, str>, Cow<'d, str>)>,
+) -> Result<(), Error> {
+    let elements = split_list(dict)?;
+    if elements.len() % 2 != 0 {
+        return Err(Error::InvalidList {
+            list: dict.to_string(),
+            message: "missing value to go with key",
+        });
+    }
+
+    let mut elements = elements.into_iter();
+    while let (Some(key), Some(value)) = (elements.next(), elements.next()) {
+        match pairs.iter_mut().find(|(existing, _)| *existing == key) {
+            Some((_, existing)) => *existing = value,
+            None => pairs.push((key, value)),
+        }
+    }
+
+    Ok(())
+}
+
+fn merge(dicts: &[Cow<'_, str>]) -> EvalResult {
+    let mut pairs = Vec::new();
+    for dict in dicts {
+        dict_pairs(dict, &mut pairs)?;
+    }
+
+    build_list(pairs.iter().flat_map(|(key, value)| vec![key, value]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::to_args;
+    use std::collections::HashMap;
+
+    fn dict(args: &[&str]) -> EvalResult {
+        Dict.eval(&mut HashMap::new(), to_args(args))
+    }
+
+    #[test]
+    fn test_unknown_subcommand() {
+        assert!(dict(&[]).is_err());
+        assert!(dict(&["nope"]).is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        assert_eq!(dict(&["merge"]), Ok(String::new()));
+        assert_eq!(
+            dict(&["merge", "a 1 b 2", "b 3 c {4 5}"]),
+            Ok("a 1 b 3 c {4 5}".to_string())
+        );
+        assert_eq!(dict(&["merge", "a 1 a 2"]), Ok("a 2".to_string()));
+        assert!(dict(&["merge", "a 1", "b"]).is_err());
+    }
+}
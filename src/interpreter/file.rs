@@ -0,0 +1,342 @@
+use std::borrow::Cow;
+
+use super::{usage, Command, Context, Error, EvalResult, Variables};
+
+/// The `file` command and its subcommands.
+///
+/// `exists`, `isdirectory`, `isfile`, `mtime`, and `size` look files up with
+/// `Context::metadata`. The other subcommands manipulate POSIX style paths, separated by `/`,
+/// without touching the filesystem. `normalize` works lexically, so relative paths stay
+/// relative.
+pub struct File<'c, C>(pub &'c C);
+
+/// The details of a file reported by `Context::metadata`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileMetadata {
+    pub is_dir: bool,
+    pub is_file: bool,
+    /// The size in bytes
+    pub size: u64,
+    /// The modification time in seconds since the Unix epoch
+    pub mtime: u64,
+}
+
+impl<'a, 'c, C> Command<'a> for File<'c, C>
+where
+    C: Context<'a>,
+{
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn eval(&self, _variables: &mut Variables, mut args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("file", &args)?;
+        let subcommand = args.remove(0);
+        match &*subcommand {
+            "dirname" => path("dirname", &args, dirname),
+            "exists" => self.query("exists", &args, |metadata| metadata.is_some()),
+            "extension" => path("extension", &args, extension),
+            "isdirectory" => self.query("isdirectory", &args, |metadata| {
+                metadata.is_some_and(|metadata| metadata.is_dir)
+            }),
+            "isfile" => self.query("isfile", &args, |metadata| {
+                metadata.is_some_and(|metadata| metadata.is_file)
+            }),
+            "join" if !args.is_empty() => Ok(join(&args)),
+            "join" => Err(usage("file", "join", "expected name ?name ...?", &args)),
+            "mtime" => self.stat("mtime", &args, |metadata| metadata.mtime),
+            "normalize" => path("normalize", &args, |name| Cow::from(normalize(name))),
+            "rootname" => path("rootname", &args, rootname),
+            "size" => self.stat("size", &args, |metadata| metadata.size),
+            "tail" => path("tail", &args, tail),
+            _ => Err(Error::Malformed {
+                cmd: "file",
+                message: "unknown subcommand",
+                got: vec![subcommand.to_string()],
+            }),
+        }
+    }
+}
+
+impl<'a, 'c, C> File<'c, C>
+where
+    C: Context<'a>,
+{
+    /// Evaluate a subcommand returning `1` or `0`, which is passed `None` when the file doesn't
+    /// exist.
+    fn query(
+        &self,
+        subcommand: &'static str,
+        args: &[Cow<'_, str>],
+        test: impl Fn(Option<FileMetadata>) -> bool,
+    ) -> EvalResult {
+        match args {
+            [name] => Ok(if test(self.0.metadata(name)?) {
+                "1"
+            } else {
+                "0"
+            }
+            .to_string()),
+            _ => Err(usage("file", subcommand, "expected name", args)),
+        }
+    }
+
+    /// Evaluate a subcommand returning a number from the metadata of a file, which is an error
+    /// when the file doesn't exist.
+    fn stat(
+        &self,
+        subcommand: &'static str,
+        args: &[Cow<'_, str>],
+        field: impl Fn(FileMetadata) -> u64,
+    ) -> EvalResult {
+        match args {
+            [name] => match self.0.metadata(name)? {
+                Some(metadata) => Ok(field(metadata).to_string()),
+                None => Err(Error::Io {
+                    message: format!("could not read \"{}\": no such file or directory", name),
+                }),
+            },
+            _ => Err(usage("file", subcommand, "expected name", args)),
+        }
+    }
+}
+
+/// Evaluate a subcommand taking a single path.
+fn path<'n>(
+    subcommand: &'static str,
+    args: &'n [Cow<'_, str>],
+    f: impl Fn(&'n str) -> Cow<'n, str>,
+) -> EvalResult {
+    match args {
+        [name] => Ok(f(name).into_owned()),
+        _ => Err(usage("file", subcommand, "expected name", args)),
+    }
+}
+
+/// Everything before the last component of `name`, ignoring trailing separators. This is `.` for
+/// a relative name without a directory, and `/` for a name in the root directory.
+fn dirname(name: &str) -> Cow<'_, str> {
+    let trimmed = name.trim_end_matches('/');
+    let dir = match trimmed.rfind('/') {
+        Some(i) => trimmed[..i].trim_end_matches('/'),
+        None if trimmed.is_empty() && name.starts_with('/') => "",
+        None => return Cow::from("."),
+    };
+    Cow::from(if dir.is_empty() { "/" } else { dir })
+}
+
+/// The last component of `name`, ignoring trailing separators.
+fn tail(name: &str) -> Cow<'_, str> {
+    let trimmed = name.trim_end_matches('/');
+    Cow::from(match trimmed.rfind('/') {
+        Some(i) => &trimmed[i + 1..],
+        None => trimmed,
+    })
+}
+
+/// The part of the last component of `name` from its last dot, or an empty string if it has no
+/// dot.
+fn extension(name: &str) -> Cow<'_, str> {
+    let start = name.rfind('/').map_or(0, |i| i + 1);
+    Cow::from(match name[start..].rfind('.') {
+        Some(i) => &name[start + i..],
+        None => "",
+    })
+}
+
+/// `name` without its extension.
+fn rootname(name: &str) -> Cow<'_, str> {
+    Cow::from(&name[..name.len() - extension(name).len()])
+}
+
+/// Join the names with separators. An absolute name discards the names before it, and empty
+/// components and trailing separators are removed.
+fn join(names: &[Cow<'_, str>]) -> String {
+    let mut joined = String::new();
+    for name in names {
+        if name.starts_with('/') {
+            joined = "/".to_string();
+        }
+        for component in name.split('/').filter(|component| !component.is_empty()) {
+            if !joined.is_empty() && !joined.ends_with('/') {
+                joined.push('/');
+            }
+            joined.push_str(component);
+        }
+    }
+    joined
+}
+
+/// Remove empty and `.` components from `name`, and resolve `..` against the preceding
+/// component. `..` components that can't be resolved are kept in relative names and dropped in
+/// absolute names.
+fn normalize(name: &str) -> String {
+    let absolute = name.starts_with('/');
+    let mut components = Vec::new();
+    for component in name.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => match components.last() {
+                Some(&last) if last != ".." => {
+                    components.pop();
+                }
+                _ if !absolute => components.push(".."),
+                _ => {}
+            },
+            _ => components.push(component),
+        }
+    }
+
+    let normalized = components.join("/");
+    if absolute {
+        format!("/{}", normalized)
+    } else if normalized.is_empty() {
+        ".".to_string()
+    } else {
+        normalized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::to_args;
+    use std::collections::HashMap;
+
+    /// A context reporting canned metadata for `dist/` and `dist/tcl.tar.gz`.
+    struct Files;
+
+    impl<'a> Context<'a> for Files {
+        fn eval(
+            &mut self,
+            _variables: &mut Variables,
+            cmd: Cow<'a, str>,
+            _args: Vec<Cow<'a, str>>,
+        ) -> EvalResult {
+            Err(Error::UnknownCommand {
+                cmd: cmd.to_string(),
+            })
+        }
+
+        fn metadata(&self, path: &str) -> Result<Option<FileMetadata>, Error> {
+            match path {
+                "dist" => Ok(Some(FileMetadata {
+                    is_dir: true,
+                    size: 4096,
+                    mtime: 1_560_000_000,
+                    ..FileMetadata::default()
+                })),
+                "dist/tcl.tar.gz" => Ok(Some(FileMetadata {
+                    is_file: true,
+                    size: 1234,
+                    mtime: 1_560_000_060,
+                    ..FileMetadata::default()
+                })),
+                "denied" => Err(Error::Io {
+                    message: "permission denied".to_string(),
+                }),
+                _ => Ok(None),
+            }
+        }
+    }
+
+    fn file(args: &[&str]) -> EvalResult {
+        File(&Files).call("file", &mut HashMap::new(), to_args(args))
+    }
+
+    fn ok(s: &str) -> EvalResult {
+        Ok(s.to_string())
+    }
+
+    #[test]
+    fn test_unknown_subcommand() {
+        assert!(file(&[]).is_err());
+        assert!(file(&["nope", "a"]).is_err());
+        assert!(file(&["join"]).is_err());
+        assert!(file(&["tail"]).is_err());
+        assert!(file(&["dirname", "a", "b"]).is_err());
+    }
+
+    #[test]
+    fn test_join() {
+        assert_eq!(file(&["join", "a", "b", "c.txt"]), ok("a/b/c.txt"));
+        assert_eq!(file(&["join", "a/", "b//", "c"]), ok("a/b/c"));
+        assert_eq!(file(&["join", "a", "/b", "c"]), ok("/b/c"));
+        assert_eq!(file(&["join", "/", "a"]), ok("/a"));
+        assert_eq!(file(&["join", "/"]), ok("/"));
+        assert_eq!(file(&["join", "a/b", "", "c"]), ok("a/b/c"));
+        assert_eq!(file(&["join", "a", ".", "b"]), ok("a/./b"));
+    }
+
+    #[test]
+    fn test_dirname() {
+        assert_eq!(file(&["dirname", "/usr/lib/libc.so"]), ok("/usr/lib"));
+        assert_eq!(file(&["dirname", "usr/lib/"]), ok("usr"));
+        assert_eq!(file(&["dirname", "a//b"]), ok("a"));
+        assert_eq!(file(&["dirname", "/usr"]), ok("/"));
+        assert_eq!(file(&["dirname", "/"]), ok("/"));
+        assert_eq!(file(&["dirname", "file.txt"]), ok("."));
+        assert_eq!(file(&["dirname", ""]), ok("."));
+    }
+
+    #[test]
+    fn test_tail() {
+        assert_eq!(file(&["tail", "/usr/lib/libc.so"]), ok("libc.so"));
+        assert_eq!(file(&["tail", "usr/lib/"]), ok("lib"));
+        assert_eq!(file(&["tail", "file.txt"]), ok("file.txt"));
+        assert_eq!(file(&["tail", "/"]), ok(""));
+    }
+
+    #[test]
+    fn test_extension_and_rootname() {
+        assert_eq!(file(&["extension", "dist/tcl-1.0.tar.gz"]), ok(".gz"));
+        assert_eq!(
+            file(&["rootname", "dist/tcl-1.0.tar.gz"]),
+            ok("dist/tcl-1.0.tar")
+        );
+        assert_eq!(file(&["extension", "dir.d/README"]), ok(""));
+        assert_eq!(file(&["rootname", "dir.d/README"]), ok("dir.d/README"));
+        assert_eq!(file(&["extension", "Makefile"]), ok(""));
+        assert_eq!(file(&["rootname", "Makefile"]), ok("Makefile"));
+        assert_eq!(file(&["extension", "name."]), ok("."));
+        assert_eq!(file(&["rootname", "name."]), ok("name"));
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            file(&["normalize", "/usr//lib/./x/../libc.so"]),
+            ok("/usr/lib/libc.so")
+        );
+        assert_eq!(file(&["normalize", "a/b/"]), ok("a/b"));
+        assert_eq!(file(&["normalize", "../a/../../b"]), ok("../../b"));
+        assert_eq!(file(&["normalize", "/../a"]), ok("/a"));
+        assert_eq!(file(&["normalize", "a/.."]), ok("."));
+        assert_eq!(file(&["normalize", "/"]), ok("/"));
+    }
+
+    #[test]
+    fn test_queries() {
+        assert_eq!(file(&["exists", "dist"]), ok("1"));
+        assert_eq!(file(&["exists", "dist/tcl.tar.gz"]), ok("1"));
+        assert_eq!(file(&["exists", "missing"]), ok("0"));
+        assert_eq!(file(&["isdirectory", "dist"]), ok("1"));
+        assert_eq!(file(&["isdirectory", "dist/tcl.tar.gz"]), ok("0"));
+        assert_eq!(file(&["isdirectory", "missing"]), ok("0"));
+        assert_eq!(file(&["isfile", "dist"]), ok("0"));
+        assert_eq!(file(&["isfile", "dist/tcl.tar.gz"]), ok("1"));
+        assert_eq!(file(&["isfile", "missing"]), ok("0"));
+        assert!(file(&["exists", "denied"]).is_err());
+        assert!(file(&["exists"]).is_err());
+    }
+
+    #[test]
+    fn test_size_and_mtime() {
+        assert_eq!(file(&["size", "dist/tcl.tar.gz"]), ok("1234"));
+        assert_eq!(file(&["mtime", "dist/tcl.tar.gz"]), ok("1560000060"));
+        assert_eq!(file(&["mtime", "dist"]), ok("1560000000"));
+        assert!(file(&["size", "missing"]).is_err());
+        assert!(file(&["mtime", "missing"]).is_err());
+        assert!(file(&["size", "a", "b"]).is_err());
+    }
+}
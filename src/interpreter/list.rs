@@ -0,0 +1,1067 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use super::{
+    bad_index, index_offset, parse_tcl_int, resolve_index, Command, Error, EvalResult, Variables,
+};
+
+/// The default maximum length in bytes of a list accepted by `split_list` or produced by
+/// `build_list`, and the default limit of the list commands, see `Context::list_limit`.
+pub const DEFAULT_LIST_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Split a Tcl list into its elements.
+pub fn split_list(list: &str) -> Result<Vec<Cow<'_, str>>, Error> {
+    split_list_with_limit(list, DEFAULT_LIST_LIMIT)
+}
+
+/// Split a Tcl list into its elements, returning an error if `list` is longer than `limit` bytes.
+///
+/// Elements are separated by whitespace and may be enclosed in braces, which are removed, or
+/// double quotes, which are removed and have backslash sequences substituted. Backslash
+/// sequences are also substituted in elements without braces or quotes.
+pub fn split_list_with_limit(list: &str, limit: usize) -> Result<Vec<Cow<'_, str>>, Error> {
+    if list.len() > limit {
+        return Err(Error::ListTooLong { limit });
+    }
+
+    let mut elements = Vec::new();
+    let mut rest = list.trim_start_matches(is_list_space);
+
+    while !rest.is_empty() {
+        let (element, remaining) = match rest.as_bytes()[0] {
+            b'{' => braced_element(list, rest)?,
+            b'"' => quoted_element(list, rest)?,
+            _ => bare_element(rest),
+        };
+        elements.push(element);
+        rest = remaining.trim_start_matches(is_list_space);
+    }
+
+    Ok(elements)
+}
+
+/// Build a Tcl list from `elements`, quoting them so that `split_list` returns them unchanged.
+pub fn build_list<I, S>(elements: I) -> Result<String, Error>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    build_list_with_limit(elements, DEFAULT_LIST_LIMIT)
+}
+
+/// Build a Tcl list from `elements`, returning an error if the list would be longer than `limit`
+/// bytes.
+pub fn build_list_with_limit<I, S>(elements: I, limit: usize) -> Result<String, Error>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut list = String::new();
+
+    for element in elements {
+        if !list.is_empty() {
+            list.push(' ');
+        }
+        push_element(&mut list, element.as_ref());
+
+        if list.len() > limit {
+            return Err(Error::ListTooLong { limit });
+        }
+    }
+
+    Ok(list)
+}
+
+/// Return `Error::ListTooLong` if a list of `count` elements would exceed `limit`.
+pub(crate) fn check_length(count: usize, limit: usize) -> Result<(), Error> {
+    if count > limit {
+        Err(Error::ListTooLong { limit })
+    } else {
+        Ok(())
+    }
+}
+
+/// Counts the elements of a list.
+///
+/// The list commands return `Error::ListTooLong` for lists they accept or produce that are
+/// longer than `limit`, in bytes or in elements.
+pub struct Llength {
+    pub limit: usize,
+}
+
+impl Default for Llength {
+    fn default() -> Self {
+        Llength {
+            limit: DEFAULT_LIST_LIMIT,
+        }
+    }
+}
+
+impl<'a> Command<'a> for Llength {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        Some(1)
+    }
+
+    fn eval(&self, _variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("llength", &args)?;
+        Ok(split_list_with_limit(&args[0], self.limit)?
+            .len()
+            .to_string())
+    }
+}
+
+/// Retrieves an element from a list, or a nested list when given several indices.
+///
+/// The indices may be given as separate arguments or as a single list of indices. An index
+/// outside the list produces an empty result.
+pub struct Lindex {
+    pub limit: usize,
+}
+
+impl Default for Lindex {
+    fn default() -> Self {
+        Lindex {
+            limit: DEFAULT_LIST_LIMIT,
+        }
+    }
+}
+
+impl<'a> Command<'a> for Lindex {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn eval(&self, _variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("lindex", &args)?;
+        let (list, indices) = (&args[0], &args[1..]);
+
+        let indices = match indices {
+            [index] => split_list(index)?,
+            _ => indices
+                .iter()
+                .map(|index| Cow::from(index.as_ref()))
+                .collect(),
+        };
+
+        let mut value = list.to_string();
+        for index in indices {
+            let mut elements = split_list_with_limit(&value, self.limit)?;
+            let position = match resolve_index(&index, elements.len())? {
+                Some(position) => position,
+                None => return Ok(String::new()),
+            };
+            value = elements.swap_remove(position).into_owned();
+        }
+
+        Ok(value)
+    }
+}
+
+/// Replaces the element at a possibly nested index of the list stored in a variable.
+///
+/// An index one past the end of a list appends the value to it.
+pub struct Lset {
+    pub limit: usize,
+}
+
+impl Default for Lset {
+    fn default() -> Self {
+        Lset {
+            limit: DEFAULT_LIST_LIMIT,
+        }
+    }
+}
+
+impl<'a> Command<'a> for Lset {
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn eval(&self, variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("lset", &args)?;
+        let (name, indices, value) = (&args[0], &args[1..args.len() - 1], &args[args.len() - 1]);
+
+        let list = variables
+            .get(name.as_ref())
+            .ok_or_else(|| Error::UnknownVariable {
+                name: name.to_string(),
+            })?;
+
+        let indices = match indices {
+            [index] => split_list(index)?,
+            _ => indices
+                .iter()
+                .map(|index| Cow::from(index.as_ref()))
+                .collect(),
+        };
+
+        let list = set_element(list, &indices, value, self.limit)?;
+        variables.insert(name.to_string(), list.clone());
+
+        Ok(list)
+    }
+}
+
+/// Appends its values as elements of the list in a variable and returns the new list.
+///
+/// With no values the current list is returned unchanged. An undefined variable is created.
+pub struct Lappend {
+    pub limit: usize,
+}
+
+impl Default for Lappend {
+    fn default() -> Self {
+        Lappend {
+            limit: DEFAULT_LIST_LIMIT,
+        }
+    }
+}
+
+impl<'a> Command<'a> for Lappend {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn eval(&self, variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("lappend", &args)?;
+        let list = variables.entry(args[0].to_string()).or_default();
+        let elements = &args[1..];
+        if !elements.is_empty() {
+            // Check the existing value is a list, but keep its original formatting
+            let existing = split_list_with_limit(list, self.limit)?.len();
+            check_length(existing.saturating_add(elements.len()), self.limit)?;
+            let appended = build_list_with_limit(elements, self.limit)?;
+            let separator = !list.trim_end_matches(is_list_space).is_empty();
+            if list.len() + usize::from(separator) + appended.len() > self.limit {
+                return Err(Error::ListTooLong { limit: self.limit });
+            }
+            if separator {
+                list.push(' ');
+            }
+            list.push_str(&appended);
+        }
+
+        Ok(list.clone())
+    }
+}
+
+/// Assigns successive elements of a list to the named variables, returning the unassigned
+/// elements.
+///
+/// Variables without a corresponding element are set to the empty string.
+pub struct Lassign {
+    pub limit: usize,
+}
+
+impl Default for Lassign {
+    fn default() -> Self {
+        Lassign {
+            limit: DEFAULT_LIST_LIMIT,
+        }
+    }
+}
+
+impl<'a> Command<'a> for Lassign {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn eval(&self, variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("lassign", &args)?;
+        let mut elements = split_list_with_limit(&args[0], self.limit)?.into_iter();
+        for name in &args[1..] {
+            let value = elements.next().unwrap_or_default();
+            variables.insert(name.to_string(), value.into_owned());
+        }
+
+        build_list_with_limit(elements, self.limit)
+    }
+}
+
+/// Sorts a list: `lsort ?options? list`.
+///
+/// Elements are compared as strings by default, or as integers with `-integer` and floating
+/// point numbers with `-real`. `-decreasing` reverses the order and `-index i` compares the
+/// `i`th element of each element, which must itself be a list. The sort is stable.
+pub struct Lsort {
+    pub limit: usize,
+}
+
+impl Default for Lsort {
+    fn default() -> Self {
+        Lsort {
+            limit: DEFAULT_LIST_LIMIT,
+        }
+    }
+}
+
+enum SortKey<'a> {
+    Ascii(Cow<'a, str>),
+    Integer(i64),
+    Real(f64),
+}
+
+impl SortKey<'_> {
+    fn compare(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (SortKey::Ascii(a), SortKey::Ascii(b)) => a.cmp(b),
+            (SortKey::Integer(a), SortKey::Integer(b)) => a.cmp(b),
+            (SortKey::Real(a), SortKey::Real(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            _ => Ordering::Equal,
+        }
+    }
+}
+
+impl<'a> Command<'a> for Lsort {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn eval(&self, _variables: &mut Variables, args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("lsort", &args)?;
+        let (list, options) = args.split_last().unwrap();
+        let mut kind = "-ascii";
+        let mut decreasing = false;
+        let mut index = None;
+
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            match option.as_ref() {
+                "-ascii" | "-integer" | "-real" => kind = option,
+                "-increasing" => decreasing = false,
+                "-decreasing" => decreasing = true,
+                "-index" => match options.next() {
+                    Some(spec) => index = Some(spec),
+                    None => return Err(lsort_error("-index requires an index", option)),
+                },
+                _ => return Err(lsort_error("unknown option", option)),
+            }
+        }
+
+        let elements = split_list_with_limit(list, self.limit)?;
+        let mut keyed = Vec::with_capacity(elements.len());
+        for element in elements {
+            let key = match index {
+                Some(spec) => {
+                    let mut sublist = split_list_with_limit(&element, self.limit)?;
+                    let position = resolve_index(spec, sublist.len())?
+                        .ok_or_else(|| lsort_error("element missing from sublist", &element))?;
+                    Cow::from(sublist.swap_remove(position).into_owned())
+                }
+                None => Cow::from(element.to_string()),
+            };
+            let key = match kind {
+                "-integer" => SortKey::Integer(parse_tcl_int(&key)?),
+                "-real" => SortKey::Real(key.trim().parse().map_err(|_| Error::Conversion {
+                    value: key.to_string(),
+                    message: "expected floating-point number",
+                })?),
+                _ => SortKey::Ascii(key),
+            };
+            keyed.push((key, element));
+        }
+
+        keyed.sort_by(|(a, _), (b, _)| {
+            let ordering = a.compare(b);
+            if decreasing {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+
+        build_list_with_limit(keyed.iter().map(|(_, element)| element), self.limit)
+    }
+}
+
+fn lsort_error(message: &'static str, got: &str) -> Error {
+    Error::Malformed {
+        cmd: "lsort",
+        message,
+        got: vec![got.to_string()],
+    }
+}
+
+fn set_element(
+    list: &str,
+    indices: &[Cow<'_, str>],
+    value: &str,
+    limit: usize,
+) -> Result<String, Error> {
+    let (index, rest) = match indices.split_first() {
+        Some((index, rest)) => (index, rest),
+        None => return Ok(value.to_string()),
+    };
+
+    let mut elements = split_list_with_limit(list, limit)?;
+    let position = index_offset(index, elements.len()).ok_or_else(|| bad_index(index))?;
+    if position < 0 || position > elements.len() as i64 {
+        return Err(Error::Malformed {
+            cmd: "lset",
+            message: "list index out of range",
+            got: vec![index.to_string()],
+        });
+    }
+
+    let position = position as usize;
+    if position == elements.len() {
+        check_length(elements.len() + 1, limit)?;
+        elements.push(Cow::from(""));
+    }
+    elements[position] = Cow::from(set_element(&elements[position], rest, value, limit)?);
+
+    build_list_with_limit(elements, limit)
+}
+
+fn is_list_space(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\n' || c == '\r' || c == '\u{b}' || c == '\u{c}'
+}
+
+fn invalid(list: &str, message: &'static str) -> Error {
+    Error::InvalidList {
+        list: list.to_string(),
+        message,
+    }
+}
+
+fn braced_element<'a>(list: &str, input: &'a str) -> Result<(Cow<'a, str>, &'a str), Error> {
+    let mut depth = 0;
+    let mut chars = input.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    let rest = &input[i + 1..];
+                    if !rest.is_empty() && !rest.starts_with(is_list_space) {
+                        return Err(invalid(
+                            list,
+                            "list element in braces followed by a character instead of space",
+                        ));
+                    }
+                    return Ok((Cow::from(&input[1..i]), rest));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Err(invalid(list, "unmatched open brace in list"))
+}
+
+fn quoted_element<'a>(list: &str, input: &'a str) -> Result<(Cow<'a, str>, &'a str), Error> {
+    let mut chars = input.char_indices().skip(1);
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '"' => {
+                let rest = &input[i + 1..];
+                if !rest.is_empty() && !rest.starts_with(is_list_space) {
+                    return Err(invalid(
+                        list,
+                        "list element in quotes followed by a character instead of space",
+                    ));
+                }
+                return Ok((substitute_backslashes(&input[1..i]), rest));
+            }
+            _ => {}
+        }
+    }
+
+    Err(invalid(list, "unmatched open quote in list"))
+}
+
+fn bare_element(input: &str) -> (Cow<'_, str>, &str) {
+    let mut chars = input.char_indices();
+    let mut end = input.len();
+
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if is_list_space(c) {
+            end = i;
+            break;
+        }
+    }
+
+    (substitute_backslashes(&input[..end]), &input[end..])
+}
+
+/// Substitute Tcl backslash sequences in `text`.
+pub(crate) fn substitute_backslashes(text: &str) -> Cow<'_, str> {
+    if !text.contains('\\') {
+        return Cow::from(text);
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('a') => result.push('\u{7}'),
+            Some('b') => result.push('\u{8}'),
+            Some('f') => result.push('\u{c}'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('t') => result.push('\t'),
+            Some('v') => result.push('\u{b}'),
+            Some('\n') => {
+                // A backslash-newline and any following spaces or tabs become a single space
+                while chars.peek() == Some(&' ') || chars.peek() == Some(&'\t') {
+                    chars.next();
+                }
+                result.push(' ');
+            }
+            Some(kind @ 'x') | Some(kind @ 'u') => {
+                let max_digits = if kind == 'x' { 2 } else { 4 };
+                match take_digits(&mut chars, 16, max_digits).and_then(std::char::from_u32) {
+                    Some(c) => result.push(c),
+                    None => result.push(kind),
+                }
+            }
+            Some(digit @ '0'..='7') => {
+                let mut value = digit.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            value = value * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                result.push(char::from(value as u8));
+            }
+            Some(c) => result.push(c),
+            // A trailing backslash is kept literally
+            None => result.push('\\'),
+        }
+    }
+
+    Cow::from(result)
+}
+
+fn take_digits(
+    chars: &mut std::iter::Peekable<std::str::Chars<'_>>,
+    radix: u32,
+    max: usize,
+) -> Option<u32> {
+    let mut value = None;
+
+    for _ in 0..max {
+        match chars.peek().and_then(|c| c.to_digit(radix)) {
+            Some(digit) => {
+                value = Some(value.unwrap_or(0) * radix + digit);
+                chars.next();
+            }
+            None => break,
+        }
+    }
+
+    value
+}
+
+fn push_element(list: &mut String, element: &str) {
+    if element.is_empty() {
+        list.push_str("{}");
+        return;
+    }
+
+    let needs_quoting = element.starts_with('#')
+        || element
+            .chars()
+            .any(|c| is_list_space(c) || "{}[]$\";\\".contains(c));
+    if !needs_quoting {
+        list.push_str(element);
+        return;
+    }
+
+    if can_brace(element) {
+        list.push('{');
+        list.push_str(element);
+        list.push('}');
+        return;
+    }
+
+    for (i, c) in element.chars().enumerate() {
+        match c {
+            '\n' => list.push_str("\\n"),
+            '\t' => list.push_str("\\t"),
+            '\r' => list.push_str("\\r"),
+            '\u{b}' => list.push_str("\\v"),
+            '\u{c}' => list.push_str("\\f"),
+            '#' if i == 0 => list.push_str("\\#"),
+            ' ' | '{' | '}' | '[' | ']' | '$' | '"' | ';' | '\\' => {
+                list.push('\\');
+                list.push(c);
+            }
+            _ => list.push(c),
+        }
+    }
+}
+
+/// Determine if an element can be quoted by enclosing it in braces.
+fn can_brace(element: &str) -> bool {
+    let mut depth = 0;
+    let mut chars = element.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                if depth == 0 {
+                    return false;
+                }
+                depth -= 1;
+            }
+            '\\' => match chars.next() {
+                // Backslash-newline would be substituted when the list is split and an odd
+                // trailing backslash would escape the closing brace
+                Some('\n') | None => return false,
+                Some(_) => {}
+            },
+            _ => {}
+        }
+    }
+
+    depth == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::to_args;
+    use std::collections::HashMap;
+
+    fn eval<'a>(command: impl Command<'a>, args: &[&'a str]) -> EvalResult {
+        command.call("test", &mut HashMap::new(), to_args(args))
+    }
+
+    #[test]
+    fn test_split_list() {
+        assert_eq!(split_list(""), Ok(vec![]));
+        assert_eq!(split_list("a b c").unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(
+            split_list("a {b c} {} \"d e\" {f {g}}").unwrap(),
+            vec!["a", "b c", "", "d e", "f {g}"]
+        );
+        assert_eq!(
+            split_list(r#"a\ b "c\td" {e\tf}"#).unwrap(),
+            vec!["a b", "c\td", r"e\tf"]
+        );
+        assert_eq!(split_list(r"\x41é\101\0").unwrap(), vec!["AéA\u{0}"]);
+    }
+
+    #[test]
+    fn test_split_list_whitespace() {
+        assert_eq!(split_list("  a  b  ").unwrap(), vec!["a", "b"]);
+        assert_eq!(split_list("\ta\t\tb\t").unwrap(), vec!["a", "b"]);
+        assert_eq!(split_list("\na\n \tb\r\n").unwrap(), vec!["a", "b"]);
+        assert_eq!(split_list(" \t\n ").unwrap(), Vec::<&str>::new());
+        assert_eq!(
+            split_list("\t{a b}\n\"c d\"  ").unwrap(),
+            vec!["a b", "c d"]
+        );
+        assert_eq!(
+            eval(Lindex::default(), &["\n  a\tb  \n", "1"]),
+            Ok("b".to_string())
+        );
+        assert_eq!(
+            eval(Lindex::default(), &["  a  b  ", "end"]),
+            Ok("b".to_string())
+        );
+    }
+
+    #[test]
+    fn test_llength() {
+        assert_eq!(eval(Llength::default(), &[""]), Ok("0".to_string()));
+        assert_eq!(eval(Llength::default(), &["  a  b  "]), Ok("2".to_string()));
+        assert_eq!(
+            eval(Llength::default(), &["a {b c} \"d e\""]),
+            Ok("3".to_string())
+        );
+        assert!(eval(Llength::default(), &[]).is_err());
+        assert!(eval(Llength::default(), &["a", "b"]).is_err());
+    }
+
+    #[test]
+    fn test_malformed_lists() {
+        let unmatched_open = || Error::InvalidList {
+            list: "a {b".to_string(),
+            message: "unmatched open brace in list",
+        };
+        assert_eq!(split_list("a {b"), Err(unmatched_open()));
+        assert_eq!(eval(Llength::default(), &["a {b"]), Err(unmatched_open()));
+        assert_eq!(
+            eval(Lindex::default(), &["a {b", "0"]),
+            Err(unmatched_open())
+        );
+        assert_eq!(eval(Lsort::default(), &["a {b"]), Err(unmatched_open()));
+        assert!(split_list("{a {b} c").is_err());
+
+        let extra_close = || Error::InvalidList {
+            list: "{a}} b".to_string(),
+            message: "list element in braces followed by a character instead of space",
+        };
+        assert_eq!(split_list("{a}} b"), Err(extra_close()));
+        assert_eq!(eval(Llength::default(), &["{a}} b"]), Err(extra_close()));
+        assert!(split_list("a {b}} c").is_err());
+
+        // As in Tcl, a close brace inside an element without braces is an ordinary character
+        assert_eq!(split_list("a b}").unwrap(), vec!["a", "b}"]);
+    }
+
+    #[test]
+    fn test_split_list_borrows_elements() {
+        let elements = split_list("plain {braced element} esc\\aped").unwrap();
+        assert!(matches!(elements[0], Cow::Borrowed("plain")));
+        assert!(matches!(elements[1], Cow::Borrowed("braced element")));
+        assert!(matches!(elements[2], Cow::Owned(_)));
+    }
+
+    #[test]
+    fn test_build_list() {
+        assert_eq!(build_list(Vec::<&str>::new()), Ok(String::new()));
+        assert_eq!(build_list(["a", "b"]), Ok("a b".to_string()));
+        assert_eq!(
+            build_list(["", "a b", "#x", "{a", "tail\\", "x$y"]),
+            Ok(r"{} {a b} {#x} \{a tail\\ {x$y}".to_string())
+        );
+    }
+
+    #[test]
+    fn test_build_list_round_trip() {
+        let elements = [
+            "",
+            "plain",
+            "with space",
+            "{unbalanced",
+            "close}",
+            "nested {braces {here}}",
+            "line\nbreak",
+            "back\\slash",
+            "trailing\\",
+            "\"quoted\"",
+            "#comment",
+            "continued\\\nline",
+        ];
+        let list = build_list(elements).unwrap();
+        assert_eq!(split_list(&list).unwrap(), elements.to_vec());
+    }
+
+    #[test]
+    fn test_list_limit() {
+        let elements = (0..1000)
+            .map(|i| format!("element{}", i))
+            .collect::<Vec<_>>();
+        let list = build_list(&elements).unwrap();
+
+        assert_eq!(
+            build_list_with_limit(&elements, 100),
+            Err(Error::ListTooLong { limit: 100 })
+        );
+        assert_eq!(
+            split_list_with_limit(&list, 100),
+            Err(Error::ListTooLong { limit: 100 })
+        );
+        assert_eq!(
+            split_list_with_limit(&list, list.len()).map(|elements| elements.len()),
+            Ok(1000)
+        );
+    }
+
+    #[test]
+    fn test_list_commands_limit() {
+        let limit = 10;
+        let too_long = || Err(Error::ListTooLong { limit });
+        let mut variables = HashMap::new();
+        let long = "a b c d e f";
+        assert_eq!(
+            Llength { limit }.eval(&mut variables, to_args(&[long])),
+            too_long()
+        );
+        assert_eq!(
+            Lindex { limit }.eval(&mut variables, to_args(&[long, "0"])),
+            too_long()
+        );
+        assert_eq!(
+            Lassign { limit }.eval(&mut variables, to_args(&[long])),
+            too_long()
+        );
+        assert_eq!(
+            Lsort { limit }.eval(&mut variables, to_args(&[long])),
+            too_long()
+        );
+        assert_eq!(
+            Lsort { limit }.eval(&mut variables, to_args(&["-index", "0", "{a b c d e f}"])),
+            too_long()
+        );
+
+        variables.insert("l".to_string(), "a b c d".to_string());
+        let mut lappend = |args: &[&'static str]| {
+            Lappend { limit }.eval(&mut variables, to_args(&[&["l"], args].concat()))
+        };
+        assert_eq!(lappend(&["e"]), Ok("a b c d e".to_string()));
+        assert_eq!(lappend(&["f"]), too_long());
+        assert_eq!(variables["l"], "a b c d e");
+        variables.insert("l".to_string(), String::new());
+        assert_eq!(
+            Lappend { limit: 3 }.eval(&mut variables, to_args(&["l", "", "", "", ""])),
+            Err(Error::ListTooLong { limit: 3 })
+        );
+
+        variables.insert("l".to_string(), "a b c d e".to_string());
+        assert_eq!(
+            Lset { limit }.eval(&mut variables, to_args(&["l", "0", "x"])),
+            Ok("x b c d e".to_string())
+        );
+        assert_eq!(
+            Lset { limit }.eval(&mut variables, to_args(&["l", "end+1", "f"])),
+            too_long()
+        );
+        assert_eq!(variables["l"], "x b c d e");
+    }
+
+    #[test]
+    fn test_lindex() {
+        assert_eq!(eval(Lindex::default(), &["a b c"]), Ok("a b c".to_string()));
+        assert_eq!(
+            eval(Lindex::default(), &["a {b c} d", "1"]),
+            Ok("b c".to_string())
+        );
+        assert_eq!(
+            eval(Lindex::default(), &["a b c", "end"]),
+            Ok("c".to_string())
+        );
+        assert_eq!(
+            eval(Lindex::default(), &["a b c", "end-2"]),
+            Ok("a".to_string())
+        );
+        assert_eq!(eval(Lindex::default(), &["a b c", "3"]), Ok(String::new()));
+        assert_eq!(eval(Lindex::default(), &["a b c", "-1"]), Ok(String::new()));
+        assert!(eval(Lindex::default(), &["a b c", "first"]).is_err());
+        assert!(eval(Lindex::default(), &[]).is_err());
+    }
+
+    fn lset(list: &str, args: &[&str]) -> Result<(String, String), Error> {
+        let mut variables = HashMap::new();
+        variables.insert("l".to_string(), list.to_string());
+        let args = std::iter::once("l")
+            .chain(args.iter().copied())
+            .map(Cow::from)
+            .collect();
+        let result = Lset::default().eval(&mut variables, args)?;
+        Ok((result, variables.remove("l").unwrap()))
+    }
+
+    #[test]
+    fn test_lset() {
+        let set = |list, args| lset(list, args).map(|(result, _)| result);
+
+        assert_eq!(set("a b c", &["1", "x"]), Ok("a x c".to_string()));
+        assert_eq!(set("a b c", &["end", "x y"]), Ok("a b {x y}".to_string()));
+        assert_eq!(set("a b c", &["x"]), Ok("x".to_string()));
+        assert_eq!(set("a b c", &["", "x"]), Ok("x".to_string()));
+        assert_eq!(
+            lset("a b c", &["0", "x"]),
+            Ok(("x b c".to_string(), "x b c".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_lset_nested() {
+        assert_eq!(
+            lset("{a b} {c d}", &["1", "0", "x"]).map(|(result, _)| result),
+            Ok("{a b} {x d}".to_string())
+        );
+        assert_eq!(
+            lset("{a b} {c d}", &["0 end", "x"]).map(|(result, _)| result),
+            Ok("{a x} {c d}".to_string())
+        );
+    }
+
+    #[test]
+    fn test_lset_append() {
+        assert_eq!(
+            lset("a b", &["end+1", "c"]).map(|(result, _)| result),
+            Ok("a b c".to_string())
+        );
+        assert_eq!(
+            lset("a b", &["2", "c"]).map(|(result, _)| result),
+            Ok("a b c".to_string())
+        );
+        assert_eq!(
+            lset("{a b} c", &["0", "end+1", "x"]).map(|(result, _)| result),
+            Ok("{a b x} c".to_string())
+        );
+        assert!(lset("a b", &["3", "c"]).is_err());
+        assert!(lset("a b", &["-1", "c"]).is_err());
+    }
+
+    #[test]
+    fn test_lset_errors() {
+        assert_eq!(
+            Lset::default().eval(&mut HashMap::new(), vec![Cow::from("l"), Cow::from("x")]),
+            Err(Error::UnknownVariable {
+                name: "l".to_string()
+            })
+        );
+        assert!(eval(Lset::default(), &["l"]).is_err());
+        assert!(lset("a b", &["first", "c"]).is_err());
+    }
+
+    #[test]
+    fn test_eval_checks_arity() {
+        let mut variables = HashMap::new();
+        assert!(Lset::default().eval(&mut variables, vec![]).is_err());
+        assert!(Lsort::default().eval(&mut variables, vec![]).is_err());
+        assert!(Lindex::default().eval(&mut variables, vec![]).is_err());
+        assert!(Lassign::default().eval(&mut variables, vec![]).is_err());
+    }
+
+    #[test]
+    fn test_lassign() {
+        let mut variables = HashMap::new();
+        let mut lassign = |args: &[&'static str]| {
+            Lassign::default().call("lassign", &mut variables, to_args(args))
+        };
+
+        assert_eq!(lassign(&["a {b c}", "x", "y"]), Ok(String::new()));
+        assert_eq!(lassign(&["1 2 {3 4} 5", "z"]), Ok("2 {3 4} 5".to_string()));
+        assert_eq!(lassign(&["q", "p", "r"]), Ok(String::new()));
+        assert_eq!(lassign(&["a b"]), Ok("a b".to_string()));
+        assert!(lassign(&[]).is_err());
+
+        let get = |name| variables.get(name).map(String::as_str);
+        assert_eq!(get("x"), Some("a"));
+        assert_eq!(get("y"), Some("b c"));
+        assert_eq!(get("z"), Some("1"));
+        assert_eq!(get("p"), Some("q"));
+        assert_eq!(get("r"), Some(""));
+    }
+
+    #[test]
+    fn test_lappend() {
+        let mut variables = HashMap::new();
+        let mut lappend = |args: &[&'static str]| {
+            Lappend::default().call("lappend", &mut variables, to_args(args))
+        };
+
+        assert_eq!(lappend(&["l"]), Ok(String::new()));
+        assert_eq!(lappend(&["l", "a"]), Ok("a".to_string()));
+        assert_eq!(lappend(&["l", "b c", "d"]), Ok("a {b c} d".to_string()));
+        assert_eq!(lappend(&["l"]), Ok("a {b c} d".to_string()));
+        assert!(lappend(&[]).is_err());
+
+        variables.insert("bad".to_string(), "{a".to_string());
+        let args = vec![Cow::from("bad"), Cow::from("b")];
+        assert!(Lappend::default()
+            .call("lappend", &mut variables, args)
+            .is_err());
+        assert_eq!(variables.get("bad").map(String::as_str), Some("{a"));
+    }
+
+    #[test]
+    fn test_lsort() {
+        assert_eq!(eval(Lsort::default(), &["c a b"]), Ok("a b c".to_string()));
+        assert_eq!(
+            eval(Lsort::default(), &["-decreasing", "c a b"]),
+            Ok("c b a".to_string())
+        );
+        assert_eq!(
+            eval(Lsort::default(), &["10 9 100"]),
+            Ok("10 100 9".to_string())
+        );
+        assert_eq!(
+            eval(Lsort::default(), &["-integer", "10 9 0x10"]),
+            Ok("9 10 0x10".to_string())
+        );
+        assert_eq!(
+            eval(Lsort::default(), &["-real", "1.5 -2 1e1"]),
+            Ok("-2 1.5 1e1".to_string())
+        );
+        assert_eq!(eval(Lsort::default(), &[""]), Ok(String::new()));
+        assert!(eval(Lsort::default(), &["-integer", "1 a"]).is_err());
+        assert!(eval(Lsort::default(), &["-nope", "a"]).is_err());
+        assert!(eval(Lsort::default(), &[]).is_err());
+    }
+
+    #[test]
+    fn test_lsort_index() {
+        assert_eq!(
+            eval(Lsort::default(), &["-index", "0", "{b 2} {a 1}"]),
+            Ok("{a 1} {b 2}".to_string())
+        );
+        assert_eq!(
+            eval(
+                Lsort::default(),
+                &["-index", "1", "-integer", "{b 2} {a 1}"]
+            ),
+            Ok("{a 1} {b 2}".to_string())
+        );
+        assert_eq!(
+            eval(
+                Lsort::default(),
+                &["-index", "1", "-integer", "{a 10} {b 9} {c 10}"]
+            ),
+            Ok("{b 9} {a 10} {c 10}".to_string())
+        );
+        assert_eq!(
+            eval(
+                Lsort::default(),
+                &["-index", "end", "-decreasing", "{x a} {y b c}"]
+            ),
+            Ok("{y b c} {x a}".to_string())
+        );
+        assert!(eval(Lsort::default(), &["-index", "2", "{a 1} {b 2}"]).is_err());
+        assert!(eval(Lsort::default(), &["-index", "{a 1}"]).is_err());
+    }
+
+    #[test]
+    fn test_lindex_nested() {
+        assert_eq!(
+            eval(Lindex::default(), &["{a b} {c d}", "1", "0"]),
+            Ok("c".to_string())
+        );
+        assert_eq!(
+            eval(Lindex::default(), &["{a b} {c d}", "1 0"]),
+            Ok("c".to_string())
+        );
+        assert_eq!(
+            eval(
+                Lindex::default(),
+                &["{{a b} {c d}} {{e f} {g h}}", "1", "end", "0"]
+            ),
+            Ok("g".to_string())
+        );
+        assert_eq!(
+            eval(Lindex::default(), &["{{a b} {c d}} {{e f} {g h}}", "1 0 1"]),
+            Ok("f".to_string())
+        );
+        assert_eq!(
+            eval(Lindex::default(), &["{a b} {c d}", "5", "0"]),
+            Ok(String::new())
+        );
+        assert_eq!(
+            eval(Lindex::default(), &["{a b} {c d}", ""]),
+            Ok("{a b} {c d}".to_string())
+        );
+    }
+}
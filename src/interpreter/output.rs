@@ -0,0 +1,100 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use super::{unknown_channel, Context, Error, EvalResult, Variables};
+
+/// Output written by `puts` while the interpreter is buffering or capturing output, see
+/// `Interpreter::set_buffered` and `Interpreter::set_capture`.
+///
+/// Consecutive writes to the same channel are joined so they can be passed on to the context in
+/// a single `Context::write` call. Only `stdout` and `stderr` are accepted, so a write to any
+/// other channel fails when `puts` is called rather than when the output is flushed.
+///
+/// A capturing buffer keeps its output until it is retrieved with `Interpreter::take_output`,
+/// instead of passing it on to the context.
+pub(crate) struct OutputBuffer {
+    line_ending: String,
+    pending: RefCell<Vec<(String, String)>>,
+    pub(crate) capture: bool,
+}
+
+impl OutputBuffer {
+    pub(crate) fn new(line_ending: &str, capture: bool) -> Self {
+        OutputBuffer {
+            line_ending: line_ending.to_string(),
+            pending: RefCell::new(Vec::new()),
+            capture,
+        }
+    }
+
+    /// Remove and return the buffered output as channel and text pairs.
+    pub(crate) fn take(&self) -> Vec<(String, String)> {
+        self.pending.replace(Vec::new())
+    }
+}
+
+impl<'a> Context<'a> for OutputBuffer {
+    fn eval(
+        &mut self,
+        _variables: &mut Variables,
+        cmd: Cow<'a, str>,
+        _args: Vec<Cow<'a, str>>,
+    ) -> EvalResult {
+        Err(Error::UnknownCommand {
+            cmd: cmd.to_string(),
+        })
+    }
+
+    fn line_ending(&self) -> &str {
+        &self.line_ending
+    }
+
+    fn write(&self, channel: &str, text: &str) -> Result<(), Error> {
+        if channel != "stdout" && channel != "stderr" {
+            return Err(unknown_channel(channel));
+        }
+
+        let mut pending = self.pending.borrow_mut();
+        match pending.last_mut() {
+            Some((last, buffered)) if last == channel => buffered.push_str(text),
+            _ => pending.push((channel.to_string(), text.to_string())),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_joins_writes_to_the_same_channel() {
+        let buffer = OutputBuffer::new("\n", false);
+        buffer.write("stdout", "a").unwrap();
+        buffer.write("stdout", "b").unwrap();
+        buffer.write("stderr", "c").unwrap();
+        buffer.write("stdout", "d").unwrap();
+        assert_eq!(
+            buffer.take(),
+            vec![
+                ("stdout".to_string(), "ab".to_string()),
+                ("stderr".to_string(), "c".to_string()),
+                ("stdout".to_string(), "d".to_string())
+            ]
+        );
+        assert!(buffer.take().is_empty());
+    }
+
+    #[test]
+    fn test_rejects_unknown_channels() {
+        let buffer = OutputBuffer::new("\n", false);
+        assert_eq!(
+            buffer.write("nowhere", "a"),
+            Err(Error::UnknownChannel {
+                channel: "nowhere".to_string()
+            })
+        );
+        assert!(buffer.take().is_empty());
+    }
+}
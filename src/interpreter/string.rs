@@ -0,0 +1,843 @@
+use std::borrow::Cow;
+
+#[cfg(feature = "graphemes")]
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::{
+    bad_index, index_offset, resolve_index, split_list, usage, Command, Error, EvalResult,
+    Variables,
+};
+
+/// The `string` command and its subcommands.
+///
+/// The `first`, `index`, `last`, `length`, `range`, and `reverse` subcommands count characters
+/// (Unicode scalar values). With the `graphemes` feature they count extended grapheme clusters
+/// instead when `graphemes` is set, which `eval_builtin` does from `Context::graphemes`.
+#[derive(Default)]
+pub struct StringCmd {
+    #[cfg(feature = "graphemes")]
+    pub graphemes: bool,
+}
+
+impl<'a> Command<'a> for StringCmd {
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn eval(&self, _variables: &mut Variables, mut args: Vec<Cow<'a, str>>) -> EvalResult {
+        self.check_args("string", &args)?;
+        let subcommand = args.remove(0);
+        match &*subcommand {
+            "cat" => Ok(args.concat()),
+            "bytelength" => length("bytelength", &args, str::len),
+            "first" => self.find("first", &args),
+            "index" => self.index(&args),
+            "last" => self.find("last", &args),
+            "length" => length("length", &args, |string| self.units(string).len()),
+            "map" => map(&args),
+            "match" => string_match(&args),
+            "range" => self.range(&args),
+            "reverse" => self.reverse(&args),
+            "totitle" => to_title(&args),
+            "trim" => trim("trim", &args, true, true),
+            "trimleft" => trim("trimleft", &args, true, false),
+            "trimprefix" => trim_affix("trimprefix", &args, |s, p| s.strip_prefix(p)),
+            "trimright" => trim("trimright", &args, false, true),
+            "trimsuffix" => trim_affix("trimsuffix", &args, |s, p| s.strip_suffix(p)),
+            "wordend" => word_boundary("wordend", &args, word_end),
+            "wordstart" => word_boundary("wordstart", &args, word_start),
+            _ => Err(Error::Malformed {
+                cmd: "string",
+                message: "unknown subcommand",
+                got: vec![subcommand.to_string()],
+            }),
+        }
+    }
+}
+
+impl StringCmd {
+    /// Split `string` into the units counted by the subcommands.
+    fn units<'s>(&self, string: &'s str) -> Vec<&'s str> {
+        #[cfg(feature = "graphemes")]
+        {
+            if self.graphemes {
+                return string.graphemes(true).collect();
+            }
+        }
+
+        string
+            .char_indices()
+            .map(|(i, c)| &string[i..i + c.len_utf8()])
+            .collect()
+    }
+
+    /// Evaluate `string index string index`, returning an empty string when the index is out of
+    /// range.
+    fn index(&self, args: &[Cow<'_, str>]) -> EvalResult {
+        match args {
+            [string, index] => {
+                let units = self.units(string);
+                Ok(match resolve_index(index, units.len())? {
+                    Some(index) => units[index].to_string(),
+                    None => String::new(),
+                })
+            }
+            _ => Err(usage("string", "index", "expected string and index", args)),
+        }
+    }
+
+    /// Evaluate `string first needle haystack ?startIndex?` or
+    /// `string last needle haystack ?lastIndex?`, returning the index of the first match at or
+    /// after `startIndex`, or of the last match starting at or before `lastIndex`, or -1 when
+    /// there is no match.
+    ///
+    /// An empty needle matches at every index, so `string first {} abc` is 0.
+    fn find(&self, subcommand: &'static str, args: &[Cow<'_, str>]) -> EvalResult {
+        let (needle, haystack, bound) = match args {
+            [needle, haystack] => (needle, haystack, None),
+            [needle, haystack, bound] => (needle, haystack, Some(bound)),
+            _ => {
+                return Err(usage(
+                    "string",
+                    subcommand,
+                    "expected needle haystack ?index?",
+                    args,
+                ))
+            }
+        };
+
+        let needle = self.units(needle);
+        let haystack = self.units(haystack);
+        let matches = |i: &i64| haystack[*i as usize..].starts_with(&needle);
+        let last_start = haystack.len() as i64 - needle.len() as i64;
+        let bound = match bound {
+            Some(spec) => Some(index_offset(spec, haystack.len()).ok_or_else(|| bad_index(spec))?),
+            None => None,
+        };
+
+        let found = if subcommand == "first" {
+            (bound.unwrap_or(0).max(0)..=last_start).find(matches)
+        } else {
+            let upper = bound.unwrap_or(haystack.len() as i64 - 1).min(last_start);
+            (0..=upper).rev().find(matches)
+        };
+
+        Ok(found.unwrap_or(-1).to_string())
+    }
+
+    /// Evaluate `string range string first last`. The range is clamped to the string and is
+    /// empty when `first` is after `last`.
+    fn range(&self, args: &[Cow<'_, str>]) -> EvalResult {
+        match args {
+            [string, first, last] => {
+                let units = self.units(string);
+                let len = units.len() as i64;
+                let index =
+                    |spec: &str| index_offset(spec, units.len()).ok_or_else(|| bad_index(spec));
+                let first = index(first)?.max(0);
+                let last = index(last)?.min(len - 1);
+                if first > last {
+                    Ok(String::new())
+                } else {
+                    Ok(units[first as usize..=last as usize].concat())
+                }
+            }
+            _ => Err(usage(
+                "string",
+                "range",
+                "expected string, first, and last",
+                args,
+            )),
+        }
+    }
+
+    /// Evaluate `string reverse string`.
+    fn reverse(&self, args: &[Cow<'_, str>]) -> EvalResult {
+        match args {
+            [string] => Ok(self.units(string).into_iter().rev().collect()),
+            _ => Err(usage("string", "reverse", "expected string", args)),
+        }
+    }
+}
+
+/// Evaluate `string map ?-nocase? mapping string`.
+///
+/// The mapping is a list of keys and replacements. At each position in the string the first key
+/// in the mapping that matches is replaced, and the replacement is not searched again. With
+/// `-nocase` keys match without regard to case, and replacements are inserted as given.
+fn map(args: &[Cow<'_, str>]) -> EvalResult {
+    let (nocase, mapping, string) = match args {
+        [flag, mapping, string] if flag == "-nocase" => (true, mapping, string),
+        [mapping, string] => (false, mapping, string),
+        _ => {
+            return Err(usage(
+                "string",
+                "map",
+                "expected ?-nocase? mapping string",
+                args,
+            ))
+        }
+    };
+
+    let elements = split_list(mapping)?;
+    if elements.len() % 2 != 0 {
+        return Err(Error::InvalidList {
+            list: mapping.to_string(),
+            message: "char map list unbalanced",
+        });
+    }
+    let pairs = elements
+        .chunks(2)
+        .filter(|pair| !pair[0].is_empty())
+        .collect::<Vec<_>>();
+
+    let mut result = String::with_capacity(string.len());
+    let mut rest = string.as_ref();
+    'outer: while let Some(c) = rest.chars().next() {
+        for pair in &pairs {
+            if let Some(len) = match_len(rest, &pair[0], nocase) {
+                result.push_str(&pair[1]);
+                rest = &rest[len..];
+                continue 'outer;
+            }
+        }
+        result.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    Ok(result)
+}
+
+/// The length in bytes of the prefix of `string` matching `key`, if it matches.
+fn match_len(string: &str, key: &str, nocase: bool) -> Option<usize> {
+    if !nocase {
+        return if string.starts_with(key) {
+            Some(key.len())
+        } else {
+            None
+        };
+    }
+
+    let mut chars = string.char_indices();
+    for k in key.chars() {
+        let (_, c) = chars.next()?;
+        if !c.to_lowercase().eq(k.to_lowercase()) {
+            return None;
+        }
+    }
+    Some(chars.next().map_or(string.len(), |(i, _)| i))
+}
+
+/// Evaluate `string match ?-nocase? pattern string`, returning `1` if the glob pattern matches
+/// the whole string and `0` otherwise.
+///
+/// `*` matches any sequence of characters, including an empty one, `?` matches any single
+/// character, `[chars]` matches any of the characters or ranges like `a-z` in the brackets, and
+/// a backslash matches the following character literally.
+fn string_match(args: &[Cow<'_, str>]) -> EvalResult {
+    let (nocase, pattern, string) = match args {
+        [flag, pattern, string] if flag == "-nocase" => (true, pattern, string),
+        [pattern, string] => (false, pattern, string),
+        _ => {
+            return Err(usage(
+                "string",
+                "match",
+                "expected ?-nocase? pattern string",
+                args,
+            ))
+        }
+    };
+
+    let fold = |text: &str| {
+        text.chars()
+            .map(|c| {
+                if nocase {
+                    c.to_lowercase().next().unwrap_or(c)
+                } else {
+                    c
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+    let matched = glob_match(&fold(pattern), &fold(string));
+    Ok(if matched { "1" } else { "0" }.to_string())
+}
+
+fn glob_match(pattern: &[char], string: &[char]) -> bool {
+    match pattern.split_first() {
+        None => string.is_empty(),
+        Some(('*', _)) => {
+            let rest = &pattern[pattern.iter().take_while(|&&c| c == '*').count()..];
+            (0..=string.len()).any(|i| glob_match(rest, &string[i..]))
+        }
+        Some((&p, rest)) => {
+            let (c, string) = match string.split_first() {
+                Some((&c, string)) => (c, string),
+                None => return false,
+            };
+            let (matched, rest) = match p {
+                '?' => (true, rest),
+                '[' => class_match(rest, c),
+                '\\' if !rest.is_empty() => (rest[0] == c, &rest[1..]),
+                _ => (p == c, rest),
+            };
+            matched && glob_match(rest, string)
+        }
+    }
+}
+
+/// Match `c` against the characters and ranges of a bracketed class, returning whether it
+/// matched and the rest of the pattern after the closing bracket.
+fn class_match(pattern: &[char], c: char) -> (bool, &[char]) {
+    let end = pattern
+        .iter()
+        .position(|&p| p == ']')
+        .unwrap_or(pattern.len());
+    let (class, rest) = (&pattern[..end], pattern.get(end + 1..).unwrap_or(&[]));
+
+    let mut matched = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            let (low, high) = if class[i] <= class[i + 2] {
+                (class[i], class[i + 2])
+            } else {
+                (class[i + 2], class[i])
+            };
+            matched |= low <= c && c <= high;
+            i += 3;
+        } else {
+            matched |= class[i] == c;
+            i += 1;
+        }
+    }
+
+    (matched, rest)
+}
+
+/// Evaluate `string length`, which counts characters, or `string bytelength`, which counts the
+/// bytes of the UTF-8 encoding.
+fn length(cmd: &'static str, args: &[Cow<'_, str>], measure: impl Fn(&str) -> usize) -> EvalResult {
+    match args {
+        [string] => Ok(measure(string).to_string()),
+        _ => Err(usage("string", cmd, "expected string", args)),
+    }
+}
+
+/// Evaluate `string totitle string ?first? ?last?`, which uppercases the first character in the
+/// range and lowercases the rest of it. Words after the first are not capitalised.
+fn to_title(args: &[Cow<'_, str>]) -> EvalResult {
+    let (string, range) = match args.split_first() {
+        Some((string, range)) if range.len() <= 2 => (string, range),
+        _ => {
+            return Err(usage(
+                "string",
+                "totitle",
+                "expected string ?first? ?last?",
+                args,
+            ))
+        }
+    };
+
+    let chars = string.chars().collect::<Vec<_>>();
+    let len = chars.len() as i64;
+    let index = |spec: &str| index_offset(spec, chars.len()).ok_or_else(|| bad_index(spec));
+    let first = range.first().map_or(Ok(0), |spec| index(spec))?.max(0);
+    let last = range
+        .get(1)
+        .map_or(Ok(len - 1), |spec| index(spec))?
+        .min(len - 1);
+    if first > last {
+        return Ok(string.to_string());
+    }
+
+    let (first, last) = (first as usize, last as usize);
+    let mut result = chars[..first].iter().collect::<String>();
+    result.extend(chars[first].to_uppercase());
+    for c in &chars[first + 1..=last] {
+        result.extend(c.to_lowercase());
+    }
+    result.extend(&chars[last + 1..]);
+
+    Ok(result)
+}
+
+/// Evaluate `string trim`, `string trimleft`, or `string trimright`, which remove any of the
+/// given characters from the start and end of a string. By default any Unicode whitespace is
+/// removed.
+fn trim(cmd: &'static str, args: &[Cow<'_, str>], left: bool, right: bool) -> EvalResult {
+    let (string, chars) = match args {
+        [string] => (string, None),
+        [string, chars] => (string, Some(chars)),
+        _ => return Err(usage("string", cmd, "expected string ?chars?", args)),
+    };
+
+    let trimmed = |c: char| chars.map_or(c.is_whitespace(), |chars| chars.contains(c));
+    let mut result = string.as_ref();
+    if left {
+        result = result.trim_start_matches(trimmed);
+    }
+    if right {
+        result = result.trim_end_matches(trimmed);
+    }
+
+    Ok(result.to_string())
+}
+
+/// Evaluate `string trimprefix` or `string trimsuffix`, which remove a literal prefix or suffix
+/// from a string when it is present. This is an extension, unlike `string trim` which removes a
+/// set of characters.
+fn trim_affix(
+    cmd: &'static str,
+    args: &[Cow<'_, str>],
+    strip: for<'s> fn(&'s str, &str) -> Option<&'s str>,
+) -> EvalResult {
+    match args {
+        [string, affix] => Ok(strip(string, affix).unwrap_or(string).to_string()),
+        _ => Err(usage("string", cmd, "expected string and affix", args)),
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Evaluate `string wordstart` or `string wordend`, passing the characters of the string and the
+/// unclamped index to `boundary`.
+fn word_boundary(
+    cmd: &'static str,
+    args: &[Cow<'_, str>],
+    boundary: fn(&[char], i64) -> usize,
+) -> EvalResult {
+    match args {
+        [string, index] => {
+            let chars = string.chars().collect::<Vec<_>>();
+            let index = index_offset(index, chars.len()).ok_or_else(|| bad_index(index))?;
+            Ok(boundary(&chars, index).to_string())
+        }
+        _ => Err(usage("string", cmd, "expected string and index", args)),
+    }
+}
+
+/// The index of the first character of the word containing `index`. A word is a run of
+/// alphanumeric characters and underscores, or any other single character.
+fn word_start(chars: &[char], index: i64) -> usize {
+    if chars.is_empty() || index <= 0 {
+        return 0;
+    }
+
+    let index = (index as usize).min(chars.len() - 1);
+    if !is_word_char(chars[index]) {
+        return index;
+    }
+    chars[..index]
+        .iter()
+        .rposition(|&c| !is_word_char(c))
+        .map_or(0, |position| position + 1)
+}
+
+/// The index just after the last character of the word containing `index`.
+fn word_end(chars: &[char], index: i64) -> usize {
+    let index = index.max(0) as usize;
+    if index >= chars.len() {
+        return chars.len();
+    }
+
+    if !is_word_char(chars[index]) {
+        return index + 1;
+    }
+    chars[index..]
+        .iter()
+        .position(|&c| !is_word_char(c))
+        .map_or(chars.len(), |position| index + position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::to_args;
+    use std::collections::HashMap;
+
+    fn string(args: &[&str]) -> EvalResult {
+        StringCmd::default().eval(&mut HashMap::new(), to_args(args))
+    }
+
+    #[test]
+    fn test_unknown_subcommand() {
+        assert!(string(&[]).is_err());
+        assert!(string(&["nope"]).is_err());
+    }
+
+    #[test]
+    fn test_cat() {
+        assert_eq!(string(&["cat"]), Ok(String::new()));
+        assert_eq!(string(&["cat", "a", "b", "c"]), Ok("abc".to_string()));
+        assert_eq!(
+            string(&["cat", "with space", " {brace", ""]),
+            Ok("with space {brace".to_string())
+        );
+    }
+
+    #[test]
+    fn test_wordstart() {
+        assert_eq!(
+            string(&["wordstart", "hello big_world", "8"]),
+            Ok("6".to_string())
+        );
+        assert_eq!(
+            string(&["wordstart", "hello big_world", "2"]),
+            Ok("0".to_string())
+        );
+        assert_eq!(
+            string(&["wordstart", "hello big_world", "5"]),
+            Ok("5".to_string())
+        );
+        assert_eq!(
+            string(&["wordstart", "hello big_world", "end"]),
+            Ok("6".to_string())
+        );
+        assert_eq!(
+            string(&["wordstart", "hello big_world", "99"]),
+            Ok("6".to_string())
+        );
+        assert_eq!(
+            string(&["wordstart", "hello big_world", "-1"]),
+            Ok("0".to_string())
+        );
+        assert_eq!(string(&["wordstart", "", "0"]), Ok("0".to_string()));
+        assert_eq!(string(&["wordstart", "a..b", "2"]), Ok("2".to_string()));
+    }
+
+    #[test]
+    fn test_wordend() {
+        assert_eq!(
+            string(&["wordend", "hello big_world", "1"]),
+            Ok("5".to_string())
+        );
+        assert_eq!(
+            string(&["wordend", "hello big_world", "9"]),
+            Ok("15".to_string())
+        );
+        assert_eq!(
+            string(&["wordend", "hello big_world", "5"]),
+            Ok("6".to_string())
+        );
+        assert_eq!(
+            string(&["wordend", "hello big_world", "end"]),
+            Ok("15".to_string())
+        );
+        assert_eq!(
+            string(&["wordend", "hello big_world", "99"]),
+            Ok("15".to_string())
+        );
+        assert_eq!(
+            string(&["wordend", "hello big_world", "-1"]),
+            Ok("5".to_string())
+        );
+        assert_eq!(string(&["wordend", "", "0"]), Ok("0".to_string()));
+    }
+
+    #[test]
+    fn test_word_boundary_errors() {
+        assert!(string(&["wordstart", "hello"]).is_err());
+        assert!(string(&["wordend", "hello", "first"]).is_err());
+    }
+
+    #[test]
+    fn test_first_and_last() {
+        assert_eq!(string(&["first", "b", "abcabc"]), Ok("1".to_string()));
+        assert_eq!(string(&["first", "bc", "abcabc", "2"]), Ok("4".to_string()));
+        assert_eq!(string(&["first", "a", "abc", "-5"]), Ok("0".to_string()));
+        assert_eq!(string(&["first", "a", "abc", "end"]), Ok("-1".to_string()));
+        assert_eq!(string(&["first", "x", "abc"]), Ok("-1".to_string()));
+        assert_eq!(string(&["first", "abcd", "abc"]), Ok("-1".to_string()));
+        assert_eq!(string(&["first", "", "abc"]), Ok("0".to_string()));
+        assert_eq!(string(&["first", "", ""]), Ok("0".to_string()));
+        assert_eq!(string(&["first", "π", "aπbπ"]), Ok("1".to_string()));
+
+        assert_eq!(string(&["last", "b", "abcabc"]), Ok("4".to_string()));
+        assert_eq!(string(&["last", "b", "abcabc", "3"]), Ok("1".to_string()));
+        assert_eq!(string(&["last", "bc", "abcabc", "4"]), Ok("4".to_string()));
+        assert_eq!(
+            string(&["last", "bc", "abcabc", "end-2"]),
+            Ok("1".to_string())
+        );
+        assert_eq!(string(&["last", "a", "abc", "-1"]), Ok("-1".to_string()));
+        assert_eq!(string(&["last", "a", "abc", "99"]), Ok("0".to_string()));
+        assert_eq!(string(&["last", "x", "abc"]), Ok("-1".to_string()));
+        assert_eq!(string(&["last", "", "abc"]), Ok("2".to_string()));
+        assert_eq!(string(&["last", "π", "aπbπ"]), Ok("3".to_string()));
+
+        assert!(string(&["first", "a"]).is_err());
+        assert!(string(&["last", "a", "b", "x"]).is_err());
+    }
+
+    #[test]
+    fn test_match() {
+        assert_eq!(string(&["match", "foo", "foo"]), Ok("1".to_string()));
+        assert_eq!(string(&["match", "foo", "foobar"]), Ok("0".to_string()));
+        assert_eq!(string(&["match", "bar", "foobar"]), Ok("0".to_string()));
+        assert_eq!(string(&["match", "foo*", "foobar"]), Ok("1".to_string()));
+        assert_eq!(string(&["match", "*bar", "foobar"]), Ok("1".to_string()));
+        assert_eq!(string(&["match", "*", ""]), Ok("1".to_string()));
+        assert_eq!(string(&["match", "a**b*", "ab"]), Ok("1".to_string()));
+        assert_eq!(string(&["match", "", ""]), Ok("1".to_string()));
+        assert_eq!(string(&["match", "?", ""]), Ok("0".to_string()));
+        assert_eq!(string(&["match", "f?o", "fóo"]), Ok("1".to_string()));
+        assert_eq!(string(&["match", "*.[ch]", "main.c"]), Ok("1".to_string()));
+        assert_eq!(string(&["match", "*.[ch]", "main.rs"]), Ok("0".to_string()));
+        assert_eq!(string(&["match", "[a-c]x", "bx"]), Ok("1".to_string()));
+        assert_eq!(string(&["match", "[c-a]x", "bx"]), Ok("1".to_string()));
+        assert_eq!(string(&["match", "[a-c]x", "dx"]), Ok("0".to_string()));
+        assert_eq!(string(&["match", "a\\*", "a*"]), Ok("1".to_string()));
+        assert_eq!(string(&["match", "a\\*", "ab"]), Ok("0".to_string()));
+        assert_eq!(string(&["match", "FOO*", "foobar"]), Ok("0".to_string()));
+        assert_eq!(
+            string(&["match", "-nocase", "FOO*", "foobar"]),
+            Ok("1".to_string())
+        );
+        assert!(string(&["match", "a"]).is_err());
+        assert!(string(&["match", "-exact", "a", "b"]).is_err());
+    }
+
+    #[test]
+    fn test_trim() {
+        assert_eq!(string(&["trim", " \t a b \n"]), Ok("a b".to_string()));
+        assert_eq!(
+            string(&["trim", "\u{a0}nbsp\u{a0}"]),
+            Ok("nbsp".to_string())
+        );
+        assert_eq!(
+            string(&["trim", "\u{2028}line\u{2029}\u{3000}"]),
+            Ok("line".to_string())
+        );
+        assert_eq!(string(&["trimleft", "  a  "]), Ok("a  ".to_string()));
+        assert_eq!(string(&["trimright", "  a  "]), Ok("  a".to_string()));
+        assert_eq!(string(&["trim", "xxaxyx", "xy"]), Ok("a".to_string()));
+        assert_eq!(string(&["trimleft", "xxaxyx", "x"]), Ok("axyx".to_string()));
+        assert_eq!(string(&["trim", " a ", ""]), Ok(" a ".to_string()));
+        assert_eq!(string(&["trim", ""]), Ok(String::new()));
+        assert!(string(&["trim"]).is_err());
+        assert!(string(&["trim", "a", "b", "c"]).is_err());
+    }
+
+    #[test]
+    fn test_trimprefix() {
+        assert_eq!(
+            string(&["trimprefix", "lib-devel", "lib"]),
+            Ok("-devel".to_string())
+        );
+        assert_eq!(
+            string(&["trimprefix", "lib-devel", "devel"]),
+            Ok("lib-devel".to_string())
+        );
+        assert_eq!(
+            string(&["trimprefix", "lib-devel", ""]),
+            Ok("lib-devel".to_string())
+        );
+        assert!(string(&["trimprefix", "lib-devel"]).is_err());
+    }
+
+    #[test]
+    fn test_trimsuffix() {
+        assert_eq!(
+            string(&["trimsuffix", "2.6.3", ".3"]),
+            Ok("2.6".to_string())
+        );
+        assert_eq!(
+            string(&["trimsuffix", "2.6.3", "2."]),
+            Ok("2.6.3".to_string())
+        );
+        assert_eq!(
+            string(&["trimsuffix", "2.6.3", ""]),
+            Ok("2.6.3".to_string())
+        );
+        assert_eq!(string(&["trimsuffix", "", "x"]), Ok(String::new()));
+    }
+
+    #[test]
+    fn test_totitle() {
+        assert_eq!(string(&["totitle", "hELLO"]), Ok("Hello".to_string()));
+        assert_eq!(
+            string(&["totitle", "hELLO wORLD"]),
+            Ok("Hello world".to_string())
+        );
+        assert_eq!(string(&["totitle", ""]), Ok(String::new()));
+        assert_eq!(string(&["totitle", "ünïcode"]), Ok("Ünïcode".to_string()));
+    }
+
+    #[test]
+    fn test_totitle_range() {
+        assert_eq!(
+            string(&["totitle", "hELLO wORLD", "6"]),
+            Ok("hELLO World".to_string())
+        );
+        assert_eq!(
+            string(&["totitle", "hELLO wORLD", "1", "3"]),
+            Ok("hEllO wORLD".to_string())
+        );
+        assert_eq!(
+            string(&["totitle", "aBCD", "end-1", "end"]),
+            Ok("aBCd".to_string())
+        );
+        assert_eq!(
+            string(&["totitle", "aBCD", "3", "1"]),
+            Ok("aBCD".to_string())
+        );
+        assert_eq!(
+            string(&["totitle", "aBCD", "-5", "99"]),
+            Ok("Abcd".to_string())
+        );
+        assert!(string(&["totitle", "aBCD", "x"]).is_err());
+        assert!(string(&["totitle", "a", "0", "0", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_length_and_bytelength() {
+        assert_eq!(string(&["length", "hello"]), Ok("5".to_string()));
+        assert_eq!(string(&["bytelength", "hello"]), Ok("5".to_string()));
+        assert_eq!(string(&["length", "café π"]), Ok("6".to_string()));
+        assert_eq!(string(&["bytelength", "café π"]), Ok("8".to_string()));
+        assert_eq!(string(&["bytelength", ""]), Ok("0".to_string()));
+        assert!(string(&["bytelength"]).is_err());
+        assert!(string(&["length", "a", "b"]).is_err());
+    }
+
+    #[cfg(feature = "graphemes")]
+    fn graphemes(args: &[&str]) -> EvalResult {
+        StringCmd { graphemes: true }.eval(&mut HashMap::new(), to_args(args))
+    }
+
+    #[test]
+    fn test_index_range_reverse() {
+        assert_eq!(string(&["index", "abc", "1"]), Ok("b".to_string()));
+        assert_eq!(string(&["index", "abc", "end"]), Ok("c".to_string()));
+        assert_eq!(string(&["index", "abc", "3"]), Ok(String::new()));
+        assert_eq!(
+            string(&["range", "abcde", "1", "end-1"]),
+            Ok("bcd".to_string())
+        );
+        assert_eq!(string(&["reverse", "abc"]), Ok("cba".to_string()));
+        assert!(string(&["index", "abc"]).is_err());
+        assert!(string(&["range", "abc", "0", "nope"]).is_err());
+    }
+
+    #[test]
+    fn test_combining_characters() {
+        let text = "cafe\u{301} \u{1f3f3}\u{fe0f}\u{200d}\u{1f308}";
+        assert_eq!(string(&["length", text]), Ok("10".to_string()));
+        assert_eq!(string(&["index", text, "3"]), Ok("e".to_string()));
+        assert_eq!(
+            string(&["reverse", "e\u{301}a"]),
+            Ok("a\u{301}e".to_string())
+        );
+    }
+
+    #[cfg(feature = "graphemes")]
+    #[test]
+    fn test_graphemes() {
+        // "e" followed by a combining acute accent, then a flag made of a ZWJ sequence
+        let text = "cafe\u{301} \u{1f3f3}\u{fe0f}\u{200d}\u{1f308}";
+        assert_eq!(graphemes(&["length", text]), Ok("6".to_string()));
+        assert_eq!(graphemes(&["index", text, "3"]), Ok("e\u{301}".to_string()));
+        assert_eq!(
+            graphemes(&["range", text, "2", "end"]),
+            Ok("fe\u{301} \u{1f3f3}\u{fe0f}\u{200d}\u{1f308}".to_string())
+        );
+        assert_eq!(
+            graphemes(&["reverse", "e\u{301}a\r\n"]),
+            Ok("\r\nae\u{301}".to_string())
+        );
+        assert_eq!(graphemes(&["bytelength", "e\u{301}"]), Ok("3".to_string()));
+
+        // A family joined with zero width joiners, a pair of regional indicators making a flag,
+        // and a Hangul syllable written as separate jamo are each one cluster
+        let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+        let flag = "\u{1f1f3}\u{1f1ff}";
+        let hangul = "\u{1100}\u{1161}\u{11a8}";
+        let text = format!("{}{}{}\r\n", family, flag, hangul);
+        assert_eq!(graphemes(&["length", &text]), Ok("4".to_string()));
+        assert_eq!(graphemes(&["index", &text, "1"]), Ok(flag.to_string()));
+        assert_eq!(
+            graphemes(&["range", &text, "2", "end"]),
+            Ok(format!("{}\r\n", hangul))
+        );
+        assert_eq!(
+            graphemes(&["length", "\u{1f1f3}\u{1f1ff}\u{1f1e6}"]),
+            Ok("2".to_string())
+        );
+        assert_eq!(graphemes(&["length", ""]), Ok("0".to_string()));
+        assert_eq!(graphemes(&["index", "", "end"]), Ok(String::new()));
+    }
+
+    #[test]
+    fn test_map() {
+        assert_eq!(
+            string(&["map", "abc 1 ab 2 a 3 1 0", "1abcaababcabababc"]),
+            Ok("01321221".to_string())
+        );
+        assert_eq!(
+            string(&["map", "1 0 ab 2 a 3 abc 1", "1abcaababcabababc"]),
+            Ok("02c322c222c".to_string())
+        );
+        assert_eq!(string(&["map", "{} x", "abc"]), Ok("abc".to_string()));
+        assert_eq!(string(&["map", "a A", "Aa"]), Ok("AA".to_string()));
+        assert!(string(&["map", "a", "abc"]).is_err());
+        assert!(string(&["map", "abc"]).is_err());
+    }
+
+    #[test]
+    fn test_map_nocase() {
+        assert_eq!(
+            string(&["map", "-nocase", "ab X", "ab AB aB Ba"]),
+            Ok("X X X Ba".to_string())
+        );
+        assert_eq!(
+            string(&["map", "-nocase", "HELLO Hi", "hello World"]),
+            Ok("Hi World".to_string())
+        );
+        assert_eq!(
+            string(&["map", "-nocase", "É e", "Éé"]),
+            Ok("ee".to_string())
+        );
+        assert_eq!(
+            string(&["map", "-nocase", "abc 1 AB 2", "ABCaBx"]),
+            Ok("12x".to_string())
+        );
+    }
+
+    #[test]
+    fn test_range_and_index_clamping() {
+        assert_eq!(string(&["range", "abc", "-3", "1"]), Ok("ab".to_string()));
+        assert_eq!(string(&["range", "abc", "1", "99"]), Ok("bc".to_string()));
+        assert_eq!(string(&["range", "abc", "2", "1"]), Ok(String::new()));
+        assert_eq!(string(&["range", "abc", "-5", "-1"]), Ok(String::new()));
+        assert_eq!(string(&["range", "abc", "3", "end"]), Ok(String::new()));
+        assert_eq!(
+            string(&["range", "abc", "end-5", "end+5"]),
+            Ok("abc".to_string())
+        );
+        assert_eq!(string(&["range", "", "0", "end"]), Ok(String::new()));
+        assert_eq!(string(&["index", "abc", "-1"]), Ok(String::new()));
+        assert_eq!(string(&["index", "abc", "end+1"]), Ok(String::new()));
+        assert_eq!(string(&["index", "abc", "end-2"]), Ok("a".to_string()));
+    }
+
+    #[test]
+    fn test_empty_string() {
+        assert_eq!(string(&["length", ""]), Ok("0".to_string()));
+        assert_eq!(string(&["bytelength", ""]), Ok("0".to_string()));
+        assert_eq!(string(&["index", "", "0"]), Ok(String::new()));
+        assert_eq!(string(&["index", "", "end"]), Ok(String::new()));
+        assert_eq!(string(&["range", "", "end", "0"]), Ok(String::new()));
+        assert_eq!(string(&["reverse", ""]), Ok(String::new()));
+        assert_eq!(string(&["totitle", ""]), Ok(String::new()));
+        assert_eq!(string(&["totitle", "", "0", "end"]), Ok(String::new()));
+        assert_eq!(string(&["map", "a b", ""]), Ok(String::new()));
+        assert_eq!(string(&["trimprefix", "", "a"]), Ok(String::new()));
+        assert_eq!(string(&["trimsuffix", "", ""]), Ok(String::new()));
+        assert_eq!(string(&["wordstart", "", "end"]), Ok("0".to_string()));
+        assert_eq!(string(&["wordend", "", "0"]), Ok("0".to_string()));
+        assert_eq!(string(&["cat"]), Ok(String::new()));
+    }
+}
@@ -1,9 +1,6 @@
-use std::borrow::Cow;
 use std::env;
-use std::fs;
 
-use tcl::interpreter::{self, Command, Context, Error, EvalResult, Interpreter, Variables};
-use tcl::parser;
+use tcl::interpreter::{Context, Interpreter};
 
 struct Env;
 
@@ -12,34 +9,18 @@ fn main() {
     if args.len() < 2 {
         eprintln!(
             "Usage {} FILE",
-            args.get(0).map(|s| s.as_str()).unwrap_or("tcl")
+            args.first().map(|s| s.as_str()).unwrap_or("tcl")
         );
         return;
     }
 
     let mut tcl = Interpreter::new(Env);
-    let script = fs::read_to_string(&args[1]).expect("Error reading input file");
-    let commands = parser::parse(&script).unwrap();
+    tcl.import_env();
 
-    match tcl.eval(commands) {
+    match tcl.eval_file(&args[1]) {
         Ok(result) => println!("{}", result),
         Err(err) => eprintln!("Error: {}", err),
     }
 }
 
-impl Context<'_> for Env {
-    fn eval(
-        &mut self,
-        variables: &mut Variables,
-        cmd: Cow<'_, str>,
-        args: Vec<Cow<'_, str>>,
-    ) -> EvalResult {
-        match &*cmd {
-            "set" => interpreter::Set.eval(variables, args),
-            "puts" => interpreter::Puts.eval(variables, args),
-            _ => Err(Error::UnknownCommand {
-                cmd: cmd.to_string(),
-            }),
-        }
-    }
-}
+impl Context<'_> for Env {}
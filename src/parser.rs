@@ -1,16 +1,18 @@
 use nom::branch::alt;
 use nom::bytes::complete::{escaped, tag, take_while, take_while1};
-use nom::character::complete::{char as chr, newline, one_of};
-use nom::combinator::{all_consuming, map};
+use nom::character::complete::{anychar, char as chr, newline};
+use nom::combinator::{all_consuming, map, recognize};
 use nom::error::ErrorKind;
 use nom::multi::{fold_many1, many0, many1};
-use nom::sequence::{delimited, preceded, terminated};
+use nom::sequence::{delimited, pair, preceded, terminated};
 use nom::{Err, IResult};
 
 // Commands are separated by newlines or semicolons
 // New lines are ignored when inside a { } group
 // When evaluating commands inside [ ] are substituted into the outer command
 // $var or ${var} is substituted with the value of the variable var
+// $var(index) is substituted with the value of the element index of the array var
+// A $ that isn't followed by a variable name is a literal $
 // Double quotes can be used to ignore special characters like space
 // Each command evaluates to a single result value
 
@@ -18,11 +20,15 @@ use nom::{Err, IResult};
 pub enum Text<'a> {
     Text(&'a str),
     Variable(&'a str),
+    // An array element `$name(index)`, where the index may contain variables
+    Element(&'a str, Vec<Text<'a>>),
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Word<'a> {
     Bare(Vec<Text<'a>>),
+    // A word inside a { } group, where backslashes are literal
+    Braced(Vec<Text<'a>>),
     Quoted(Vec<Text<'a>>),
     Subst(Command<'a>),
 }
@@ -36,6 +42,28 @@ pub enum Token<'a> {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Command<'a>(pub(crate) Vec<Word<'a>>);
 
+/// A syntax error, reported by `parse_all`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ParseError {
+    /// The line the error is on, starting from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    /// Describe a failure to parse `script` at `remaining`, which is the unparsed end of it.
+    pub(crate) fn at(script: &str, remaining: &str) -> Self {
+        let offset = script.len() - remaining.len();
+        let line = script[..offset].matches('\n').count() + 1;
+        let message = match remaining.lines().next() {
+            Some(text) if !text.trim().is_empty() => format!("unexpected '{}'", text.trim()),
+            _ => String::from("unexpected end of script"),
+        };
+
+        ParseError { line, message }
+    }
+}
+
 fn is_space(c: char) -> bool {
     c == ' ' || c == '\t'
 }
@@ -53,12 +81,20 @@ fn is_grouped_word(c: char) -> bool {
     c != '{' && c != '}' && c != '[' && c != ']' && c != '"' && c != '$' && !is_space(c)
 }
 
+// A backslash-newline and any following spaces or tabs are treated as a single space
+fn continuation(input: &str) -> IResult<&str, &str> {
+    recognize(preceded(tag("\\\n"), take_while(is_space)))(input)
+}
+
 fn ws(input: &str) -> IResult<&str, &str> {
-    take_while(|c| is_space(c) || c == '\n')(input)
+    recognize(many0(alt((
+        take_while1(|c| is_space(c) || c == '\n'),
+        continuation,
+    ))))(input)
 }
 
 fn sep(input: &str) -> IResult<&str, &str> {
-    take_while(is_space)(input)
+    recognize(many0(alt((take_while1(is_space), continuation))))(input)
 }
 
 fn inline_variable_char(c: char) -> bool {
@@ -69,17 +105,75 @@ fn bracketed_variable_char(c: char) -> bool {
     c != '{' && c != '}'
 }
 
+// Bare text ends at a backslash-newline, which separates words. Any other character following a
+// backslash is part of the text, even if it would otherwise end the word.
 fn text(input: &str) -> IResult<&str, Text<'_>> {
-    map(take_while1(is_word), Text::Text)(input)
+    let mut chars = input.char_indices().peekable();
+    let mut end = input.len();
+
+    while let Some((i, c)) = chars.next() {
+        if !is_word(c) {
+            end = i;
+            break;
+        }
+        if c == '\\' {
+            match chars.peek() {
+                Some((_, '\n')) => {
+                    end = i;
+                    break;
+                }
+                Some(_) => {
+                    chars.next();
+                }
+                None => {}
+            }
+        }
+    }
+
+    if end == 0 {
+        Err(Err::Error((input, ErrorKind::TakeWhile1)))
+    } else {
+        Ok((&input[end..], Text::Text(&input[..end])))
+    }
 }
 
 fn escaped_text(input: &str) -> IResult<&str, Text<'_>> {
     let allowed = take_while1(|c| c != '\\' && c != '"' && c != '$');
-    map(escaped(allowed, '\\', one_of(r#"\$"n"#)), Text::Text)(input)
+    map(escaped(allowed, '\\', anychar), Text::Text)(input)
 }
 
 fn variable(input: &str) -> IResult<&str, Text<'_>> {
-    map(alt((inline_variable, bracketed_variable)), Text::Variable)(input)
+    alt((
+        array_element,
+        map(alt((inline_variable, bracketed_variable)), Text::Variable),
+    ))(input)
+}
+
+fn array_index_char(c: char) -> bool {
+    c != ')' && c != '\n' && c != '$'
+}
+
+fn array_index_text(input: &str) -> IResult<&str, Text<'_>> {
+    map(take_while1(array_index_char), Text::Text)(input)
+}
+
+// Array elements are stored as variables named `var(index)`, with variables in the index
+// substituted first
+fn array_element(input: &str) -> IResult<&str, Text<'_>> {
+    map(
+        preceded(
+            chr('$'),
+            pair(
+                take_while1(inline_variable_char),
+                delimited(
+                    chr('('),
+                    many0(alt((array_index_text, variable, dollar))),
+                    chr(')'),
+                ),
+            ),
+        ),
+        |(name, index)| Text::Element(name, index),
+    )(input)
 }
 
 fn inline_variable(input: &str) -> IResult<&str, &str> {
@@ -90,18 +184,28 @@ fn bracketed_variable(input: &str) -> IResult<&str, &str> {
     delimited(tag("${"), take_while1(bracketed_variable_char), chr('}'))(input)
 }
 
+// A `$` that doesn't start a variable, such as one followed by a space or at the end of the
+// input, is a literal dollar sign
+fn dollar(input: &str) -> IResult<&str, Text<'_>> {
+    map(tag("$"), Text::Text)(input)
+}
+
 fn text_or_variable(input: &str) -> IResult<&str, Text<'_>> {
-    alt((text, variable))(input)
+    alt((text, variable, dollar))(input)
 }
 
 fn escaped_text_or_variable(input: &str) -> IResult<&str, Text<'_>> {
-    alt((escaped_text, variable))(input)
+    alt((escaped_text, variable, dollar))(input)
 }
 
 fn word(input: &str) -> IResult<&str, Word<'_>> {
     map(many1(text_or_variable), Word::Bare)(input)
 }
 
+fn braced_word(input: &str) -> IResult<&str, Word<'_>> {
+    map(many1(text_or_variable), Word::Braced)(input)
+}
+
 fn quoted_word(input: &str) -> IResult<&str, Word<'_>> {
     map(
         alt((
@@ -123,7 +227,10 @@ fn word_or_quoted(input: &str) -> IResult<&str, Word<'_>> {
 fn group(input: &str) -> IResult<&str, Vec<Word<'_>>> {
     preceded(
         chr('{'),
-        terminated(many0(preceded(ws, word_or_quoted)), preceded(ws, chr('}'))),
+        terminated(
+            many0(preceded(ws, alt((braced_word, quoted_word)))),
+            preceded(ws, chr('}')),
+        ),
     )(input)
 }
 
@@ -172,6 +279,37 @@ pub fn parse(input: &str) -> Result<Vec<Command<'_>>, Err<(&str, ErrorKind)>> {
     all_consuming(empty_or_commands)(input).map(|(_remaining, commands)| commands)
 }
 
+/// Parse as much of a script as possible, returning the commands that could be parsed and an
+/// error for each command that couldn't.
+///
+/// After an error parsing resumes at the next line, so several independent errors can be
+/// reported at once. A script that `parse` accepts gives the same commands and no errors.
+pub fn parse_all(input: &str) -> (Vec<Command<'_>>, Vec<ParseError>) {
+    let mut commands = Vec::new();
+    let mut errors = Vec::new();
+    let mut rest = match just_ws(input) {
+        Ok(("", _)) => "",
+        _ => input,
+    };
+
+    while !rest.is_empty() {
+        let remaining = match command(rest) {
+            Ok((remaining, command)) if remaining.is_empty() || remaining.starts_with('\n') => {
+                commands.push(command);
+                rest = remaining.trim_start_matches('\n');
+                continue;
+            }
+            Ok((remaining, _)) => remaining,
+            Err(Err::Error((remaining, _))) | Err(Err::Failure((remaining, _))) => remaining,
+            Err(Err::Incomplete(_)) => "",
+        };
+        errors.push(ParseError::at(input, remaining));
+        rest = remaining.find('\n').map_or("", |i| &remaining[i + 1..]);
+    }
+
+    (commands, errors)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -186,6 +324,11 @@ mod tests {
         Word::Bare(vec![Text::Text(s)])
     }
 
+    // Word::Braced from str
+    fn g(s: &str) -> Word<'_> {
+        Word::Braced(vec![Text::Text(s)])
+    }
+
     // Variable from str
     fn v(s: &str) -> Word<'_> {
         Word::Bare(vec![Text::Variable(s)])
@@ -217,6 +360,31 @@ mod tests {
             quoted_word(r#""Many\\escapes\n\"here\"""#),
             Ok(("", q(r#"Many\\escapes\n\"here\""#)))
         );
+        assert_eq!(quoted_word(r#""a\tb""#), Ok(("", q(r"a\tb"))));
+        assert_eq!(quoted_word("\"a\\\nb\""), Ok(("", q("a\\\nb"))));
+    }
+
+    #[test]
+    fn test_trailing_backslash() {
+        assert_eq!(quoted_word(r#""end\\""#), Ok(("", q(r"end\\"))));
+        assert!(quoted_word(r#""end\"#).is_err());
+        assert!(parse(r#"puts "end\"#).is_err());
+        assert_eq!(
+            parse(r"puts end\"),
+            Ok(vec![Command(vec![b("puts"), b(r"end\")])])
+        );
+    }
+
+    #[test]
+    fn test_multi_line_quoted_word() {
+        assert_eq!(quoted_word("\"line1\nline2\""), Ok(("", q("line1\nline2"))));
+        assert_eq!(
+            parse("puts \"line1\nline2\"\nputs done"),
+            Ok(vec![
+                Command(vec![b("puts"), q("line1\nline2")]),
+                Command(vec![b("puts"), b("done")])
+            ])
+        );
     }
 
     #[test]
@@ -240,7 +408,7 @@ mod tests {
             Ok((" { world }", vec![b("hello")]))
         );
         assert_eq!(word_list("hello\nworld"), Ok(("\nworld", vec![b("hello")])));
-        assert_eq!(word_list("").is_err(), true);
+        assert!(word_list("").is_err());
     }
 
     #[test]
@@ -262,6 +430,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_array_variable() {
+        let element = |name, index| Text::Element(name, vec![Text::Text(index)]);
+        assert_eq!(
+            word("$env(HOME)"),
+            Ok(("", Word::Bare(vec![element("env", "HOME")])))
+        );
+        assert_eq!(
+            word("$a(x)/y"),
+            Ok(("", Word::Bare(vec![element("a", "x"), Text::Text("/y")])))
+        );
+        assert_eq!(
+            quoted_word(r#""$a(x y)""#),
+            Ok(("", Word::Quoted(vec![element("a", "x y")])))
+        );
+        assert_eq!(
+            word("$a()"),
+            Ok(("", Word::Bare(vec![Text::Element("a", vec![])])))
+        );
+        assert_eq!(
+            word("$env($k)"),
+            Ok((
+                "",
+                Word::Bare(vec![Text::Element("env", vec![Text::Variable("k")])])
+            ))
+        );
+        assert_eq!(
+            word("$a(x,$i)"),
+            Ok((
+                "",
+                Word::Bare(vec![Text::Element(
+                    "a",
+                    vec![Text::Text("x,"), Text::Variable("i")]
+                )])
+            ))
+        );
+        assert_eq!(
+            word("$a(x"),
+            Ok(("", Word::Bare(vec![Text::Variable("a"), Text::Text("(x")])))
+        );
+    }
+
     #[test]
     fn test_parse_bracketed_variable() {
         assert_eq!(
@@ -304,17 +514,52 @@ mod tests {
 
     #[test]
     fn test_group() {
-        assert_eq!(group("{ world }"), Ok(("", vec![b("world")])));
-        assert_eq!(group("{world}"), Ok(("", vec![b("world")])));
+        assert_eq!(group("{ world }"), Ok(("", vec![g("world")])));
+        assert_eq!(group("{world}"), Ok(("", vec![g("world")])));
         assert_eq!(
             group("{ hello\nworld }"),
-            Ok(("", vec![b("hello"), b("world")]))
+            Ok(("", vec![g("hello"), g("world")]))
         );
         assert_eq!(
             group("{\n  hello\n  world\n}"),
-            Ok(("", vec![b("hello"), b("world")]))
+            Ok(("", vec![g("hello"), g("world")]))
+        );
+        assert!(group("{ world").is_err());
+    }
+
+    #[test]
+    fn test_backslash_newline() {
+        assert_eq!(
+            parse("puts a \\\n    b\nputs c"),
+            Ok(vec![
+                Command(vec![b("puts"), b("a"), b("b")]),
+                Command(vec![b("puts"), b("c")])
+            ])
+        );
+        assert_eq!(
+            parse("puts a\\\nb"),
+            Ok(vec![Command(vec![b("puts"), b("a"), b("b")])])
+        );
+        assert_eq!(group("{a \\\n  b}"), Ok(("", vec![g("a"), g("b")])));
+        assert_eq!(group(r"{a\nb}"), Ok(("", vec![g(r"a\nb")])));
+        assert_eq!(
+            parse("puts a\\\\\nputs b"),
+            Ok(vec![
+                Command(vec![b("puts"), b(r"a\\")]),
+                Command(vec![b("puts"), b("b")])
+            ])
         );
-        assert_eq!(group("{ world").is_err(), true);
+    }
+
+    #[test]
+    fn test_bare_word_backslashes() {
+        assert_eq!(
+            parse(r"set x a\ b"),
+            Ok(vec![Command(vec![b("set"), b("x"), b(r"a\ b")])])
+        );
+        assert_eq!(word(r"\$x"), Ok(("", b(r"\$x"))));
+        assert_eq!(word(r#"\[a\]\{\}\"q"#), Ok(("", b(r#"\[a\]\{\}\"q"#))));
+        assert_eq!(group(r"{a\}b}"), Ok(("", vec![g(r"a\}b")])));
     }
 
     #[test]
@@ -347,7 +592,7 @@ mod tests {
     fn test_command() {
         assert_eq!(
             command("hello { world }"),
-            Ok(("", Command(vec![b("hello"), b("world")])))
+            Ok(("", Command(vec![b("hello"), g("world")])))
         );
         assert_eq!(
             command("hello \"{[ world ]}\""),
@@ -359,17 +604,17 @@ mod tests {
         );
         assert_eq!(
             command("demo {\n  hello\n  world\n}"),
-            Ok(("", Command(vec![b("demo"), b("hello"), b("world")])))
+            Ok(("", Command(vec![b("demo"), g("hello"), g("world")])))
         );
         assert_eq!(
             command("demo {\n  hello world\n}"),
-            Ok(("", Command(vec![b("demo"), b("hello"), b("world")])))
+            Ok(("", Command(vec![b("demo"), g("hello"), g("world")])))
         );
         assert_eq!(
             command("hello { world }\ndemo {\n  hello\n  world\n}\n"),
             Ok((
                 "\ndemo {\n  hello\n  world\n}\n",
-                Command(vec![b("hello"), b("world")])
+                Command(vec![b("hello"), g("world")])
             ))
         );
         assert_eq!(
@@ -413,7 +658,7 @@ mod tests {
     fn test_parse_single() {
         assert_eq!(
             parse("hello { world }"),
-            Ok(vec![Command(vec![b("hello"), b("world")])])
+            Ok(vec![Command(vec![b("hello"), g("world")])])
         );
     }
 
@@ -422,8 +667,8 @@ mod tests {
         assert_eq!(
             parse("hello { world }\ndemo {\n  hello\n  world\n}\n"),
             Ok(vec![
-                Command(vec![b("hello"), b("world")]),
-                Command(vec![b("demo"), b("hello"), b("world")])
+                Command(vec![b("hello"), g("world")]),
+                Command(vec![b("demo"), g("hello"), g("world")])
             ])
         );
     }
@@ -469,13 +714,13 @@ mod tests {
                 ]),
                 Command(vec![
                     b("makedepends"),
-                    b("zlib-devel"),
-                    b("readline-devel"),
-                    b("libffi-devel"),
-                    b("libressl-devel"),
-                    b("gdbm-devel"),
-                    b("libyaml-devel"),
-                    b("pango-devel")
+                    g("zlib-devel"),
+                    g("readline-devel"),
+                    g("libffi-devel"),
+                    g("libressl-devel"),
+                    g("gdbm-devel"),
+                    g("libyaml-devel"),
+                    g("pango-devel")
                 ]),
                 Command(vec![b("checkdepends"), b("tzdata")]),
                 Command(vec![b("short_desc"), q("Ruby programming language")]),
@@ -493,8 +738,8 @@ mod tests {
                         Text::Variable("version"),
                         Text::Text(".tar.bz2")
                     ]),
-                    b("checksum"),
-                    b("dd638bf42059182c1d04af0d5577131d4ce70b79105231c4cc0a60de77b14f2e")
+                    g("checksum"),
+                    g("dd638bf42059182c1d04af0d5577131d4ce70b79105231c4cc0a60de77b14f2e")
                 ])
             ])
         )
@@ -514,4 +759,85 @@ mod tests {
     //         assert!(parse(&script).is_ok())
     //     }
     // }
+
+    #[test]
+    fn test_literal_dollar() {
+        assert_eq!(parse("puts $"), Ok(vec![Command(vec![b("puts"), b("$")])]));
+        assert_eq!(
+            parse("puts a$ $("),
+            Ok(vec![Command(vec![
+                b("puts"),
+                Word::Bare(vec![Text::Text("a"), Text::Text("$")]),
+                Word::Bare(vec![Text::Text("$"), Text::Text("(")]),
+            ])])
+        );
+        assert_eq!(
+            parse("puts \"cost $ $5\""),
+            Ok(vec![Command(vec![
+                b("puts"),
+                Word::Quoted(vec![
+                    Text::Text("cost "),
+                    Text::Text("$"),
+                    Text::Text(" "),
+                    Text::Variable("5")
+                ]),
+            ])])
+        );
+        assert_eq!(
+            parse("puts \"end $\""),
+            Ok(vec![Command(vec![
+                b("puts"),
+                Word::Quoted(vec![Text::Text("end "), Text::Text("$")]),
+            ])])
+        );
+        assert_eq!(
+            parse("puts {$}"),
+            Ok(vec![Command(vec![b("puts"), g("$")])])
+        );
+    }
+
+    #[test]
+    fn test_parse_all() {
+        let script = "set a ]\nputs ok\nset b }\nputs done";
+        assert!(parse(script).is_err());
+        assert_eq!(
+            parse_all(script),
+            (
+                vec![
+                    Command(vec![b("puts"), b("ok")]),
+                    Command(vec![b("puts"), b("done")])
+                ],
+                vec![
+                    ParseError {
+                        line: 1,
+                        message: "unexpected ']'".to_string()
+                    },
+                    ParseError {
+                        line: 3,
+                        message: "unexpected '}'".to_string()
+                    }
+                ]
+            )
+        );
+
+        // Parsing resumes on the line after an error, even inside an unclosed group
+        let (commands, errors) = parse_all("puts {a\nb");
+        assert_eq!(commands, vec![Command(vec![b("b")])]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 1);
+    }
+
+    #[test]
+    fn test_parse_all_agrees_with_parse() {
+        for script in &[
+            "",
+            "  \n",
+            "puts a",
+            "set a 1\nputs $a\n",
+            "set a {x\ny}\n\nputs [get a]  ",
+            "puts \"a\nb\"\nputs \\\n  c",
+        ] {
+            assert_eq!(parse_all(script), (parse(script).unwrap(), Vec::new()));
+        }
+    }
 }